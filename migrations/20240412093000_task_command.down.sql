@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS task_run;
+
+ALTER TABLE task
+    DROP COLUMN output_topic,
+    DROP COLUMN command,
+    DROP COLUMN kind,
+    MODIFY script VARCHAR(255) NOT NULL;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+ALTER TABLE task
+    MODIFY script VARCHAR(255) NOT NULL DEFAULT '',
+    ADD kind VARCHAR(32) NOT NULL DEFAULT 'script',
+    ADD command JSON,
+    ADD output_topic VARCHAR(255);
+
+CREATE TABLE
+    task_run (
+        id CHAR(21) PRIMARY KEY,
+        task_id CHAR(21) NOT NULL,
+        started_at timestamp(3) NOT NULL,
+        finished_at timestamp(3) NOT NULL,
+        success BOOLEAN NOT NULL,
+        exit_code INT,
+        output MEDIUMTEXT,
+        error TEXT,
+        INDEX (task_id, started_at),
+        FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE
+    );
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE task
+    DROP COLUMN finished,
+    DROP COLUMN executed_count;
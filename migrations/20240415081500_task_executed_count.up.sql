@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD executed_count INT NOT NULL DEFAULT 0,
+    ADD finished BOOLEAN NOT NULL DEFAULT FALSE;
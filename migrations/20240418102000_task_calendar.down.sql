@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS calendar_entry;
+DROP TABLE IF EXISTS calendar;
+
+ALTER TABLE task
+    DROP COLUMN calendars,
+    DROP COLUMN end_at,
+    DROP COLUMN start_at;
@@ -0,0 +1,23 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD start_at timestamp NULL DEFAULT NULL,
+    ADD end_at timestamp NULL DEFAULT NULL,
+    ADD calendars JSON;
+
+CREATE TABLE
+    calendar (
+        id CHAR(21) PRIMARY KEY,
+        name VARCHAR(255) NOT NULL UNIQUE,
+        remark TEXT
+    );
+
+CREATE TABLE
+    calendar_entry (
+        id CHAR(21) PRIMARY KEY,
+        calendar_id CHAR(21) NOT NULL,
+        start_date DATE NOT NULL,
+        end_date DATE,
+        remark TEXT,
+        INDEX (calendar_id, start_date),
+        FOREIGN KEY (calendar_id) REFERENCES calendar (id) ON DELETE CASCADE
+    );
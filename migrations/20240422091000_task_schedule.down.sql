@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE task
+    DROP COLUMN schedule,
+    MODIFY cron VARCHAR(255) NOT NULL;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE task
+    MODIFY cron VARCHAR(255) NOT NULL DEFAULT '',
+    ADD schedule JSON;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD manage_tasks BOOLEAN NOT NULL DEFAULT FALSE;
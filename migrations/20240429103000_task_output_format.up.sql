@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD output_format VARCHAR(32) NOT NULL DEFAULT 'auto';
@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE
+    secret (
+        name VARCHAR(255) PRIMARY KEY,
+        value TEXT NOT NULL,
+        remark TEXT,
+        updated_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP
+    );
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE header
+    ADD connection VARCHAR(255) NOT NULL DEFAULT 'default';
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE header
+    DROP COLUMN default_type;
+
+ALTER TABLE message
+    DROP COLUMN external;
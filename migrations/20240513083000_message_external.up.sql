@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE message
+    ADD external BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE header
+    ADD default_type VARCHAR(255);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE message
+    DROP COLUMN encoding,
+    MODIFY raw TEXT NOT NULL,
+    MODIFY html TEXT NOT NULL;
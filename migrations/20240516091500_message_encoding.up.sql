@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE message
+    ADD encoding VARCHAR(16) NOT NULL DEFAULT 'utf8',
+    MODIFY raw MEDIUMTEXT NOT NULL,
+    MODIFY html MEDIUMTEXT NOT NULL;
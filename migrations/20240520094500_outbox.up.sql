@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE
+    outbox (
+        id CHAR(21) PRIMARY KEY,
+        seq BIGINT NOT NULL AUTO_INCREMENT UNIQUE,
+        connection VARCHAR(255) NOT NULL,
+        topic VARCHAR(255) NOT NULL,
+        message JSON NOT NULL,
+        status VARCHAR(16) NOT NULL DEFAULT 'pending',
+        attempts INT UNSIGNED NOT NULL DEFAULT 0,
+        last_error TEXT,
+        created_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+        INDEX (connection, status, seq)
+    );
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE message
+    DROP INDEX expires_at,
+    DROP COLUMN expires_at;
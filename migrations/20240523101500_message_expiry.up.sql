@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE message
+    ADD expires_at timestamp NULL DEFAULT NULL,
+    ADD INDEX (expires_at);
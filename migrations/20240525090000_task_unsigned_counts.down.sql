@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE task
+    MODIFY executed_count INT NOT NULL DEFAULT 0,
+    MODIFY max_executions INT;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE task
+    MODIFY executed_count INT UNSIGNED NOT NULL DEFAULT 0,
+    MODIFY max_executions INT UNSIGNED;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD output_connection VARCHAR(255);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE task
+    ADD next_fire timestamp NULL DEFAULT NULL;
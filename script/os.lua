@@ -100,61 +100,6 @@ end
 
 
 
--- 获取 Linux CPU 负载百分比
-local function get_l_cpu_load_percentage()
-    local command = "top -bn1 | grep 'Cpu(s)' | awk '{print $2 + $4}'"
-    local handle = io.popen(command)
-    local result = handle and handle:read("*a") or ""
-    if handle then
-        handle:close()
-    end
-    return tonumber(result)
-end
-
--- 获取 Linux CPU 时钟速度 (虚拟机好像没法正常获取到)
-local function get_l_cpu_clock_speed()
-    local command = "lscpu | grep 'CPU MHz' | awk '{print $3}'"
-    local handle = io.popen(command)
-    local result = handle and handle:read("*a") or ""
-    if handle then
-        handle:close()
-    end
-    return tonumber(result)
-end
-
--- 获取 Linux 系统运行时间
-local function get_l_system_uptime()
-    local command = "awk '{print $1}' /proc/uptime"
-    local handle = io.popen(command)
-    local result = handle and handle:read("*a") or ""
-    if handle then
-        handle:close()
-    end
-    return tonumber(result)
-end
-
--- 获取Linux 空闲物理内存
-local function get_l_free_memory_size()
-    local command = "grep -E '^MemFree:' /proc/meminfo | awk '{print $2}'"
-    local handle = io.popen(command)
-    local result = handle and handle:read("*a") or ""
-    if handle then
-        handle:close()
-    end
-    return tonumber(result)
-end
-
--- 获取总可见内存大小
-local function get_l_total_memory_size()
-    local command = "grep -E '^MemTotal:' /proc/meminfo | awk '{print $2}'"
-    local handle = io.popen(command)
-    local result = handle and handle:read("*a") or ""
-    if handle then
-        handle:close()
-    end
-    return tonumber(result)
-end
-
 local os_info = nil
 
 if package.config:sub(1, 1) == '\\' then -- windows
@@ -168,12 +113,16 @@ if package.config:sub(1, 1) == '\\' then -- windows
     }
 elseif package.config:sub(1, 1) == '/' then -- unix linux
     os_type = "linux"                       -- linux
+    local tyme_os = require("tyme.sys")
+    local memory = tyme_os.memory()
     os_info = {
-        cpu_load_percentage = get_l_cpu_load_percentage(),
-        cpu_clock_speed = get_l_cpu_clock_speed(),
-        system_uptime = get_l_system_uptime(),
-        free_memory_size = get_l_free_memory_size(),
-        total_memory_size = get_l_total_memory_size()
+        cpu_load_percentage = tyme_os.cpu().load,
+        system_uptime = tyme_os.uptime(),
+        free_memory_size = memory.free // 1024,
+        total_memory_size = memory.total // 1024,
+        load_average = tyme_os.load_average(),
+        disk = tyme_os.disk("/"),
+        network = tyme_os.network()
     }
 else
     os_type = "macOS" -- macos
@@ -0,0 +1,9 @@
+-- run with `tyme-server test`, every global `test_*` function is a test
+function test_send_markdown()
+    test.run("test.lua")
+
+    local published = test.published()
+    test.assert_eq(#published, 1, "published count")
+    test.assert_eq(published[1].topic, "test/test")
+    test.assert_eq(published[1].type, "text/markdown")
+end
@@ -1,8 +1,9 @@
 ---@diagnostic disable: unused-function, undefined-global
-local function send_markdown(topic, qos, ephemeral, content, callback)
+-- `connection` names the broker to publish to, the default one when nil
+local function send_markdown(topic, qos, ephemeral, content, callback, connection)
     local co = coroutine.create(
         function()
-            tyme_sys:send_markdown(topic, qos, ephemeral, content)
+            tyme_sys:send_markdown(topic, qos, ephemeral, content, connection)
             if callback then
                 callback()
             end
@@ -11,10 +12,10 @@ local function send_markdown(topic, qos, ephemeral, content, callback)
     coroutine.resume(co)
 end
 
-local function send_json(topic, qos, ephemeral, content, callback)
+local function send_json(topic, qos, ephemeral, content, callback, connection)
     local co = coroutine.create(
         function()
-            tyme_sys:send_json(topic, qos, ephemeral, content)
+            tyme_sys:send_json(topic, qos, ephemeral, content, connection)
             if callback then
                 callback()
             end
@@ -23,10 +24,68 @@ local function send_json(topic, qos, ephemeral, content, callback)
     coroutine.resume(co)
 end
 
+local function sleep(millis)
+    tyme_sys:sleep(millis)
+end
+
+local function wait_for(topic, timeout)
+    return tyme_sys:wait_for(topic, timeout)
+end
+
+-- milliseconds since the epoch, faked by the script test runner
+local function now()
+    return tyme_sys:now()
+end
+
+local function kv_get(key)
+    return tyme_sys:kv_get(key)
+end
+
+local function kv_set(key, value)
+    tyme_sys:kv_set(key, value)
+end
+
+-- the decrypted value of a secret, nil when there is no such secret
+local function secret(name)
+    return tyme_sys:secret(name)
+end
+
+local function log(level, msg)
+    tyme_sys:log(level, msg)
+end
+
+-- the task management functions only work for tasks allowed to manage tasks
+local function task_list()
+    return tyme_sys:task_list()
+end
+
+local function task_start(id)
+    tyme_sys:task_start(id)
+end
+
+local function task_stop(id)
+    tyme_sys:task_stop(id)
+end
+
+local function task_run_now(id, params)
+    tyme_sys:task_run_now(id, params)
+end
+
 local sys_config = tyme_sys.sys_config
 
 return {
     send_markdown = send_markdown,
     send_json = send_json,
+    sleep = sleep,
+    wait_for = wait_for,
+    now = now,
+    kv_get = kv_get,
+    kv_set = kv_set,
+    secret = secret,
+    log = log,
+    task_list = task_list,
+    task_start = task_start,
+    task_stop = task_stop,
+    task_run_now = task_run_now,
     sys_config = sys_config
 }
@@ -21,6 +21,18 @@ pub struct StartParam {
 
     #[structopt(short = "i", long = "init")]
     pub init: bool,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Run the `*_test.lua` scripts of the script dir against a mocked host
+    Test {
+        /// only run the files whose name contains this
+        filter: Option<String>,
+    },
 }
 
 impl StartParam {
@@ -0,0 +1,47 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// A named list of dates on which the tasks using it do not fire
+#[derive(Deserialize, Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct Calendar {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub remark: Option<String>,
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub entries: Vec<CalendarEntry>,
+}
+
+/// A single excluded date, or an inclusive range when `end_date` is set
+#[derive(Deserialize, Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct CalendarEntry {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub calendar_id: String,
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub remark: Option<String>,
+}
+
+impl Calendar {
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("name is empty");
+        }
+        Ok(())
+    }
+}
+
+impl CalendarEntry {
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self
+            .end_date
+            .is_some_and(|end_date| end_date < self.start_date)
+        {
+            anyhow::bail!("end date is before start date");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Seconds a command may run when the task does not set a timeout
+const DEFAULT_TIMEOUT: u64 = 60;
+
+/// Captured stdout/stderr are cut to this many bytes
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// An executable run by a command task
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CommandSpec {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// relative to the work dir, defaults to the work dir itself
+    pub working_dir: Option<String>,
+    /// seconds
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CommandOutput {
+    /// `None` when the process was killed by a signal or the timeout
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+
+    pub fn to_markdown(&self, name: &str) -> String {
+        let status = if self.timed_out {
+            "timed out".to_string()
+        } else {
+            match self.exit_code {
+                Some(code) => format!("exited with code {}", code),
+                None => "was killed".to_string(),
+            }
+        };
+
+        let mut markdown = format!("**{}** {}\n", name, status);
+        if !self.stdout.is_empty() {
+            markdown.push_str(&format!("\n```text\n{}\n```\n", self.stdout.trim_end()));
+        }
+        if !self.stderr.is_empty() {
+            markdown.push_str(&format!(
+                "\nstderr:\n\n```text\n{}\n```\n",
+                self.stderr.trim_end()
+            ));
+        }
+        markdown
+    }
+}
+
+impl CommandSpec {
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.program.is_empty() {
+            anyhow::bail!("program is empty");
+        }
+
+        self.working_dir()?;
+        Ok(())
+    }
+
+    fn working_dir(&self) -> anyhow::Result<PathBuf> {
+        let word_dir = crate::start_param.word_dir.clone();
+
+        let Some(working_dir) = &self.working_dir else {
+            return Ok(word_dir);
+        };
+
+        // only plain relative paths, the command must stay inside the work dir
+        let valid = Path::new(working_dir)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !valid {
+            anyhow::bail!("Invalid working dir:{}", working_dir);
+        }
+
+        Ok(word_dir.join(working_dir))
+    }
+
+    pub async fn run(&self) -> anyhow::Result<CommandOutput> {
+        let timeout = Duration::from_secs(self.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+        let child = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .env_remove(crate::secret::SECRET_KEY_ENV)
+            .envs(&self.env)
+            .current_dir(self.working_dir()?)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // dropping the child on timeout kills it
+        match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => {
+                let output = output?;
+                Ok(CommandOutput {
+                    exit_code: output.status.code(),
+                    stdout: truncate_output(&output.stdout),
+                    stderr: truncate_output(&output.stderr),
+                    timed_out: false,
+                })
+            }
+            Err(_) => Ok(CommandOutput {
+                exit_code: None,
+                stdout: String::new(),
+                stderr: format!("Timed out after {}s", timeout.as_secs()),
+                timed_out: true,
+            }),
+        }
+    }
+}
+
+fn truncate_output(output: &[u8]) -> String {
+    if output.len() > MAX_OUTPUT {
+        format!(
+            "{}\n... (truncated)",
+            String::from_utf8_lossy(&output[..MAX_OUTPUT])
+        )
+    } else {
+        String::from_utf8_lossy(output).into_owned()
+    }
+}
@@ -23,9 +23,15 @@ lazy_static! {
     };
 }
 
+/// Name of the connection made with `mqtt_config`
+pub const DEFAULT_CONNECTION: &str = "default";
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct TymeConfig {
     pub mqtt_config: MQTTConfig,
+    /// brokers connected to besides the one of `mqtt_config`
+    #[serde(default)]
+    pub brokers: Vec<BrokerConfig>,
     pub web_console_config: WebConsoleConfig,
     pub database: String,
 
@@ -44,6 +50,10 @@ pub struct MQTTConfig {
     pub keep_alive_interval: Option<u64>,
     pub auth: Auth,
     pub ssl: Ssl,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    #[serde(default)]
+    pub outbox: Outbox,
 }
 
 #[derive(Deserialize, Serialize, Clone, Default)]
@@ -53,16 +63,97 @@ pub struct Auth {
     pub password: Option<String>,
 }
 
-/// can watch paho_mqtt::SslOptions
+/// can watch paho_mqtt::SslOptions, relative paths are resolved under `workdir/ssl`
 #[derive(Deserialize, Serialize, Clone, Default)]
 pub struct Ssl {
     pub enable: bool,
+    /// CA certificates in PEM, the system defaults when unset
     pub trust_store: Option<String>,
+    /// client certificate in PEM for mutual TLS, may also hold its private key
     pub key_store: Option<PathBuf>,
+    /// private key of the client certificate, when not in `key_store`
     pub private_key: Option<PathBuf>,
     pub private_key_password: Option<String>,
+    /// directory of CA certificates
     pub ca_path: Option<PathBuf>,
+    /// ALPN protocols offered to the broker
     pub protos: Option<Vec<String>>,
+    /// check the certificate chain of the broker, true when unset
+    #[serde(default)]
+    pub verify_server_cert: Option<bool>,
+    /// check that the certificate of the broker matches its host name, true when unset
+    #[serde(default)]
+    pub verify_hostname: Option<bool>,
+}
+
+impl Ssl {
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.private_key.is_some() && self.key_store.is_none() {
+            anyhow::bail!("private_key needs the client certificate in key_store");
+        }
+        if self.private_key_password.is_some()
+            && self.private_key.is_none()
+            && self.key_store.is_none()
+        {
+            anyhow::bail!("private_key_password is set without a private key");
+        }
+        Ok(())
+    }
+}
+
+/// A named broker connection, headers and sent messages refer to it by name
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BrokerConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub mqtt_config: MQTTConfig,
+}
+
+/// How the client gets back to the broker after losing the connection
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Reconnect {
+    /// delay before the first attempt, in milliseconds
+    pub initial_delay: u64,
+    /// upper bound of the delay, in milliseconds
+    pub max_delay: u64,
+    /// the delay is multiplied by this after every failed attempt
+    pub multiplier: f64,
+    /// share of the delay taken off at random, from 0 to 1
+    pub jitter: f64,
+    /// subscribe to every header again when the broker did not resume the session
+    pub resubscribe: bool,
+}
+
+/// Messages published while the broker is unreachable are queued in the database
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Outbox {
+    /// pending messages kept for the connection
+    pub capacity: u32,
+    /// what gives way when the queue is full
+    pub overflow: OverflowPolicy,
+    /// sent, dropped and failed messages are removed after this many hours
+    pub retention_hours: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// the oldest pending message is dropped to make room
+    #[default]
+    DropOldest,
+    /// the new message is dropped
+    DropNewest,
+}
+
+impl OverflowPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverflowPolicy::DropOldest => "drop_oldest",
+            OverflowPolicy::DropNewest => "drop_newest",
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -104,11 +195,7 @@ impl TymeConfig {
                 ));
             }
         };
-        if config.mqtt_config.ssl.enable && config.mqtt_config.ssl.trust_store.is_none() {
-            return Err(anyhow::anyhow!(
-                "trust_store cannot be empty when opening ssl connection"
-            ));
-        }
+        config.mqtt_config.ssl.check()?;
 
         if config.mqtt_config.auth.enable
             && (config.mqtt_config.auth.username.is_none()
@@ -119,12 +206,55 @@ impl TymeConfig {
             ));
         }
 
+        config.check_brokers()?;
+
         config.first_start = false;
         config.config_file = config_file;
 
         Ok(config)
     }
 
+    fn check_brokers(&self) -> anyhow::Result<()> {
+        let mut names = vec![DEFAULT_CONNECTION];
+        for broker in &self.brokers {
+            if broker.name.is_empty() {
+                anyhow::bail!("Every broker needs a name");
+            }
+            if names.contains(&broker.name.as_str()) {
+                anyhow::bail!("Broker name {} is used twice", broker.name);
+            }
+            names.push(&broker.name);
+            broker.mqtt_config.check()?;
+        }
+        Ok(())
+    }
+
+    /// Every broker connection, `mqtt_config` first
+    pub fn connections(&self) -> Vec<BrokerConfig> {
+        let mut connections = vec![BrokerConfig {
+            name: DEFAULT_CONNECTION.to_string(),
+            mqtt_config: self.mqtt_config.clone(),
+        }];
+        connections.extend(self.brokers.iter().cloned());
+        connections
+    }
+
+    /// The config without credentials, as handed to scripts
+    pub fn redacted(mut self) -> Self {
+        for mqtt_config in std::iter::once(&mut self.mqtt_config).chain(
+            self.brokers
+                .iter_mut()
+                .map(|broker| &mut broker.mqtt_config),
+        ) {
+            mqtt_config.auth.password = None;
+            mqtt_config.ssl.private_key_password = None;
+        }
+        self.web_console_config.password = String::new();
+        self.web_console_config.api_token = None;
+        self.database = String::new();
+        self
+    }
+
     ///Generate initial config file
     pub fn initial() -> anyhow::Result<()> {
         let conf = crate::start_param.word_dir.join("config.toml");
@@ -145,14 +275,16 @@ impl TymeConfig {
     }
 
     pub fn get_clint_name(&self) -> String {
-        format!("tyme-server-{}", self.mqtt_config.client_id)
+        self.mqtt_config.get_clint_name()
     }
 
     pub async fn update(&self) -> anyhow::Result<()> {
+        self.check_brokers()?;
         let config_str = toml_edit::ser::to_string_pretty(&self)?;
         let config_file = {
             let mut loc_config = TYME_CONFIG.lock();
             loc_config.mqtt_config = self.mqtt_config.clone();
+            loc_config.brokers = self.brokers.clone();
             loc_config.web_console_config = self.web_console_config.clone();
             loc_config.config_file.clone()
         };
@@ -172,12 +304,12 @@ impl TymeConfig {
 }
 
 impl MQTTConfig {
+    pub fn get_clint_name(&self) -> String {
+        format!("tyme-server-{}", self.client_id)
+    }
+
     pub fn check(&self) -> anyhow::Result<()> {
-        if self.ssl.enable && self.ssl.trust_store.is_none() {
-            return Err(anyhow::anyhow!(
-                "trust_store cannot be empty when opening ssl connection"
-            ));
-        }
+        self.ssl.check()?;
 
         if self.auth.enable && (self.auth.username.is_none() || self.auth.password.is_none()) {
             return Err(anyhow::anyhow!(
@@ -192,6 +324,7 @@ impl Default for TymeConfig {
     fn default() -> Self {
         Self {
             mqtt_config: Default::default(),
+            brokers: Default::default(),
             web_console_config: Default::default(),
             first_start: true,
             config_file: Default::default(),
@@ -200,6 +333,28 @@ impl Default for TymeConfig {
     }
 }
 
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            max_delay: 60_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            resubscribe: true,
+        }
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            overflow: OverflowPolicy::default(),
+            retention_hours: 24,
+        }
+    }
+}
+
 impl Default for WebConsoleConfig {
     fn default() -> Self {
         Self {
@@ -215,6 +370,7 @@ impl<'a> IntoLua<'a> for TymeConfig {
     fn into_lua(self, lua: &'a mlua::Lua) -> mlua::Result<mlua::Value> {
         let table = lua.create_table()?;
         table.set("mqtt_config", self.mqtt_config.into_lua(lua)?)?;
+        table.set("brokers", self.brokers.into_lua(lua)?)?;
         table.set("web_console_config", self.web_console_config.into_lua(lua)?)?;
         table.into_lua(lua)
     }
@@ -232,6 +388,40 @@ impl<'a> IntoLua<'a> for MQTTConfig {
         )?;
         table.set("auth", self.auth.into_lua(lua)?)?;
         table.set("ssl", self.ssl.into_lua(lua)?)?;
+        table.set("reconnect", self.reconnect.into_lua(lua)?)?;
+        table.set("outbox", self.outbox.into_lua(lua)?)?;
+        table.into_lua(lua)
+    }
+}
+
+impl<'a> IntoLua<'a> for BrokerConfig {
+    fn into_lua(self, lua: &'a mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = self.mqtt_config.into_lua(lua)?;
+        if let mlua::Value::Table(table) = &table {
+            table.set("name", self.name)?;
+        }
+        Ok(table)
+    }
+}
+
+impl<'a> IntoLua<'a> for Reconnect {
+    fn into_lua(self, lua: &'a mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("initial_delay", self.initial_delay.into_lua(lua)?)?;
+        table.set("max_delay", self.max_delay.into_lua(lua)?)?;
+        table.set("multiplier", self.multiplier.into_lua(lua)?)?;
+        table.set("jitter", self.jitter.into_lua(lua)?)?;
+        table.set("resubscribe", self.resubscribe.into_lua(lua)?)?;
+        table.into_lua(lua)
+    }
+}
+
+impl<'a> IntoLua<'a> for Outbox {
+    fn into_lua(self, lua: &'a mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("capacity", self.capacity.into_lua(lua)?)?;
+        table.set("overflow", self.overflow.as_str().into_lua(lua)?)?;
+        table.set("retention_hours", self.retention_hours.into_lua(lua)?)?;
         table.into_lua(lua)
     }
 }
@@ -240,9 +430,7 @@ impl<'a> IntoLua<'a> for WebConsoleConfig {
     fn into_lua(self, lua: &'a mlua::Lua) -> mlua::Result<mlua::Value> {
         let table = lua.create_table()?;
         table.set("username", self.username.into_lua(lua)?)?;
-        table.set("password", self.password.into_lua(lua)?)?;
         table.set("port", self.port.into_lua(lua)?)?;
-        table.set("api_token", self.api_token.into_lua(lua)?)?;
         table.into_lua(lua)
     }
 }
@@ -252,7 +440,6 @@ impl<'a> IntoLua<'a> for Auth {
         let table = lua.create_table()?;
         table.set("enable", self.enable.into_lua(lua)?)?;
         table.set("username", self.username.into_lua(lua)?)?;
-        table.set("password", self.password.into_lua(lua)?)?;
         table.into_lua(lua)
     }
 }
@@ -282,10 +469,6 @@ impl<'a> IntoLua<'a> for Ssl {
                 .map(|p| p.as_os_str().to_str().into_lua(lua))
                 .unwrap_or_else(|| Ok(mlua::Value::Nil))?,
         )?;
-        table.set(
-            "private_key_password",
-            self.private_key_password.into_lua(lua)?,
-        )?;
         table.set(
             "ca_path",
             self.ca_path
@@ -294,6 +477,8 @@ impl<'a> IntoLua<'a> for Ssl {
                 .unwrap_or_else(|| Ok(mlua::Value::Nil))?,
         )?;
         table.set("protos", self.protos.into_lua(lua)?)?;
+        table.set("verify_server_cert", self.verify_server_cert.into_lua(lua)?)?;
+        table.set("verify_hostname", self.verify_hostname.into_lua(lua)?)?;
         table.into_lua(lua)
     }
 }
@@ -303,6 +488,8 @@ impl<'a> IntoLua<'a> for Header {
         let table = lua.create_table()?;
         table.set("topic", self.topic.into_lua(lua)?)?;
         table.set("qos", self.qos.into_lua(lua)?)?;
+        table.set("connection", self.connection.into_lua(lua)?)?;
+        table.set("default_type", self.default_type.into_lua(lua)?)?;
         table.into_lua(lua)
     }
 }
@@ -1,3 +1,5 @@
+use std::{future::Future, time::Instant};
+
 use futures::executor::block_on;
 
 use sqlx::{
@@ -6,7 +8,16 @@ use sqlx::{
     MySql, Pool,
 };
 
-use crate::{header::Header, message::RecMessage, task::Task, tyme_config, web_console::PageParam};
+use crate::{
+    calendar::{Calendar, CalendarEntry},
+    header::Header,
+    message::{RecMessage, SendMessage},
+    outbox::{OutboxMessage, OutboxStatus},
+    secret::{self, Secret},
+    task::{Task, TaskRun},
+    tyme_config,
+    web_console::PageParam,
+};
 
 lazy_static! {
     static ref POOL: Pool<MySql> = {
@@ -33,6 +44,20 @@ lazy_static! {
     };
 }
 
+/// Records the count and latency of the queries of this module
+trait Observe: Future + Sized {
+    async fn observe(self) -> Self::Output;
+}
+
+impl<F: Future<Output = Result<T, sqlx::Error>>, T> Observe for F {
+    async fn observe(self) -> Self::Output {
+        let started = Instant::now();
+        let result = self.await;
+        crate::metrics::db_query(started.elapsed(), result.is_ok());
+        result
+    }
+}
+
 pub async fn db_init() -> anyhow::Result<()> {
     let migrate: Migrator = sqlx::migrate!();
 
@@ -40,21 +65,27 @@ pub async fn db_init() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Open and idle connections of the pool
+pub fn pool_status() -> (u32, usize) {
+    (POOL.size(), POOL.num_idle())
+}
+
 pub async fn get_msg_by_id(id: &str) -> anyhow::Result<Option<RecMessage>> {
     let msg:Option<RecMessage> = sqlx::query_as(
-        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html from message m where m.id = ?"#
+        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html,m.external,m.encoding,m.expires_at from message m where m.id = ?"#
          ).bind(id)
-        .fetch_optional(&*POOL)
+        .fetch_optional(&*POOL).observe()
         .await?;
 
     Ok(msg)
 }
 
 impl RecMessage {
+    /// Store the message under its own id, raw download links rendered into the html use it
     pub async fn insert(&self, header_id: &String) -> anyhow::Result<String> {
-        let id = nanoid::nanoid!();
+        let id = self.id.clone();
         sqlx::query(r#"
-        insert into message(id, topic, qos, retain, mine, sender, receiver, type, raw, html, header_id) values (?,?,?,?,?,?,?,?,?,?,?)
+        insert into message(id, topic, qos, retain, mine, sender, receiver, type, raw, html, header_id, external, encoding, expires_at) values (?,?,?,?,?,?,?,?,?,?,?,?,?,?)
         "#).bind(&id)
         .bind(&self.topic)
         .bind(&self.qos)
@@ -66,16 +97,28 @@ impl RecMessage {
         .bind(&self.content.raw)
         .bind(&self.content.html)
         .bind(&header_id)
-        .execute(&*POOL).await?;
+        .bind(self.external)
+        .bind(self.content.encoding.as_str())
+        .bind(self.expires_at)
+        .execute(&*POOL).observe().await?;
 
         Ok(id)
     }
 
+    /// Remove the stored messages whose TTL ran out, returns how many were removed
+    pub async fn purge_expired() -> anyhow::Result<u64> {
+        let result = sqlx::query(r#"delete from message where expires_at is not null and expires_at < ?"#)
+            .bind(chrono::Local::now())
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn get_msg_by_header(header_id: &str) -> anyhow::Result<Vec<RecMessage>> {
         let msgs:Vec<RecMessage> = sqlx::query_as(
-        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html from message m,header h where m.header_id = h.id and h.id = ? order by timestamp desc"#
+        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html,m.external,m.encoding,m.expires_at from message m,header h where m.header_id = h.id and h.id = ? order by timestamp desc"#
          ).bind(header_id)
-        .fetch_all(&*POOL)
+        .fetch_all(&*POOL).observe()
         .await?;
         Ok(msgs)
     }
@@ -85,7 +128,7 @@ impl RecMessage {
             r#"select count(*) from message m,header h where m.header_id = h.id and h.id = ?"#,
         )
         .bind(header_id)
-        .fetch_one(&*POOL)
+        .fetch_one(&*POOL).observe()
         .await?;
         Ok(count.0)
     }
@@ -95,11 +138,11 @@ impl RecMessage {
         page_param: &PageParam,
     ) -> anyhow::Result<Vec<RecMessage>> {
         let msgs:Vec<RecMessage> = sqlx::query_as(
-        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html from message m,header h where m.header_id = h.id and h.id = ? order by timestamp desc limit ? offset ?"#
+        r#"select m.id,m.topic,m.qos,m.retain,m.mine,m.timestamp,m.sender,m.receiver,m.type,m.raw,m.html,m.external,m.encoding,m.expires_at from message m,header h where m.header_id = h.id and h.id = ? order by timestamp desc limit ? offset ?"#
          ).bind(header_id)
         .bind(page_param.page_size as i64)
         .bind((page_param.page_size * page_param.page_num) as i64)
-        .fetch_all(&*POOL).await?;
+        .fetch_all(&*POOL).observe().await?;
         Ok(msgs)
     }
 }
@@ -107,15 +150,25 @@ impl RecMessage {
 impl Task {
     pub async fn insert(&self) -> anyhow::Result<String> {
         let id = nanoid::nanoid!();
-        sqlx::query(r#"insert into task (id, script, cron, name, remark, max_executions, auto_start) values ( ?, ?, ?, ?, ?, ?, ?)"#)
+        sqlx::query(r#"insert into task (id, kind, script, command, cron, schedule, name, remark, max_executions, auto_start, output_topic, start_at, end_at, calendars, manage_tasks, output_format, output_connection) values ( ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#)
             .bind(&id)
+            .bind(self.kind.as_str())
             .bind(&self.script)
+            .bind(&self.command)
             .bind(&self.cron)
+            .bind(&self.schedule)
             .bind(&self.name)
             .bind(&self.remark)
             .bind(&self.max_executions)
             .bind(&self.auto_start)
-            .execute(&*POOL)
+            .bind(&self.output_topic)
+            .bind(self.start_at)
+            .bind(self.end_at)
+            .bind(&self.calendars)
+            .bind(self.manage_tasks)
+            .bind(self.output_format.as_str())
+            .bind(&self.output_connection)
+            .execute(&*POOL).observe()
             .await?;
 
         Ok(id)
@@ -124,66 +177,443 @@ impl Task {
     pub async fn remove(id: &String) -> anyhow::Result<()> {
         sqlx::query(r#"delete from task where id = ?"#)
             .bind(id)
-            .execute(&*POOL)
+            .execute(&*POOL).observe()
             .await?;
         Ok(())
     }
 
     pub async fn update(&self, id: &String) -> anyhow::Result<()> {
-        sqlx::query(r#"update task set script = ?, cron = ?, name = ?, remark = ?, max_executions = ?, auto_start = ? where id = ?"#)
+        sqlx::query(r#"update task set kind = ?, script = ?, command = ?, cron = ?, schedule = ?, name = ?, remark = ?, max_executions = ?, auto_start = ?, output_topic = ?, finished = ?, start_at = ?, end_at = ?, calendars = ?, manage_tasks = ?, output_format = ?, output_connection = ?, next_fire = ? where id = ?"#)
+            .bind(self.kind.as_str())
             .bind(&self.script)
+            .bind(&self.command)
             .bind(&self.cron)
+            .bind(&self.schedule)
             .bind(&self.name)
             .bind(&self.remark)
             .bind(&self.max_executions)
             .bind(&self.auto_start)
+            .bind(&self.output_topic)
+            .bind(self.finished)
+            .bind(self.start_at)
+            .bind(self.end_at)
+            .bind(&self.calendars)
+            .bind(self.manage_tasks)
+            .bind(self.output_format.as_str())
+            .bind(&self.output_connection)
+            .bind(self.next_fire)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn increment_executed_count(id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"update task set executed_count = executed_count + 1 where id = ?"#)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    /// The task ran out of executions, it is not started again
+    pub async fn finish(id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"update task set finished = true, auto_start = false where id = ?"#)
             .bind(id)
-            .execute(&*POOL)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_paused(id: &String, paused: bool) -> anyhow::Result<()> {
+        sqlx::query(r#"update task set paused = ? where id = ?"#)
+            .bind(paused)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_next_fire(id: &String, next_fire: Option<chrono::DateTime<chrono::Local>>) -> anyhow::Result<()> {
+        sqlx::query(r#"update task set next_fire = ? where id = ?"#)
+            .bind(next_fire)
+            .bind(id)
+            .execute(&*POOL).observe()
             .await?;
         Ok(())
     }
 
     pub async fn get_all_task() -> anyhow::Result<Vec<Task>> {
-        let tasks = sqlx::query_as(r#"select t.id,t.script,t.cron,t.name,t.remark,t.max_executions,t.auto_start from task t"#)
-            .fetch_all(&*POOL)
+        let tasks = sqlx::query_as(r#"select t.id,t.kind,t.script,t.command,t.cron,t.schedule,t.name,t.remark,t.max_executions,t.auto_start,t.output_topic,t.executed_count,t.finished,t.start_at,t.end_at,t.calendars,t.manage_tasks,t.output_format,t.output_connection,t.paused,t.next_fire from task t"#)
+            .fetch_all(&*POOL).observe()
             .await?;
         Ok(tasks)
     }
 }
 
+/// Runs kept per task, older ones are removed on insert
+const TASK_RUN_RETENTION: i64 = 100;
+
+impl TaskRun {
+    pub async fn insert(&self) -> anyhow::Result<String> {
+        let id = nanoid::nanoid!();
+        sqlx::query(r#"insert into task_run (id, task_id, started_at, finished_at, success, exit_code, output, error) values (?, ?, ?, ?, ?, ?, ?, ?)"#)
+            .bind(&id)
+            .bind(&self.task_id)
+            .bind(self.started_at)
+            .bind(self.finished_at)
+            .bind(self.success)
+            .bind(self.exit_code)
+            .bind(&self.output)
+            .bind(&self.error)
+            .execute(&*POOL).observe()
+            .await?;
+
+        sqlx::query(r#"delete from task_run where task_id = ? and id not in (select id from (select r.id from task_run r where r.task_id = ? order by r.started_at desc limit ?) latest)"#)
+            .bind(&self.task_id)
+            .bind(&self.task_id)
+            .bind(TASK_RUN_RETENTION)
+            .execute(&*POOL).observe()
+            .await?;
+
+        Ok(id)
+    }
+
+    pub async fn get_by_task(task_id: &String) -> anyhow::Result<Vec<TaskRun>> {
+        let runs = sqlx::query_as(r#"select r.id,r.task_id,r.started_at,r.finished_at,r.success,r.exit_code,r.output,r.error from task_run r where r.task_id = ? order by r.started_at desc"#)
+            .bind(task_id)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(runs)
+    }
+
+    /// The latest run of every task
+    pub async fn get_last_runs() -> anyhow::Result<Vec<TaskRun>> {
+        let runs = sqlx::query_as(r#"select r.id,r.task_id,r.started_at,r.finished_at,r.success,r.exit_code,r.output,r.error from task_run r where r.started_at = (select max(l.started_at) from task_run l where l.task_id = r.task_id)"#)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(runs)
+    }
+}
+
+impl Calendar {
+    pub async fn insert(&self) -> anyhow::Result<String> {
+        let id = nanoid::nanoid!();
+        sqlx::query(r#"insert into calendar (id, name, remark) values (?, ?, ?)"#)
+            .bind(&id)
+            .bind(&self.name)
+            .bind(&self.remark)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn update(&self, id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"update calendar set name = ?, remark = ? where id = ?"#)
+            .bind(&self.name)
+            .bind(&self.remark)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove(id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"delete from calendar where id = ?"#)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_all_calendar() -> anyhow::Result<Vec<Calendar>> {
+        let mut calendars: Vec<Calendar> =
+            sqlx::query_as(r#"select c.id,c.name,c.remark from calendar c order by c.name"#)
+                .fetch_all(&*POOL).observe()
+                .await?;
+
+        let entries: Vec<CalendarEntry> = sqlx::query_as(r#"select e.id,e.calendar_id,e.start_date,e.end_date,e.remark from calendar_entry e order by e.start_date"#)
+            .fetch_all(&*POOL).observe()
+            .await?;
+
+        for entry in entries {
+            if let Some(calendar) = calendars.iter_mut().find(|c| c.id == entry.calendar_id) {
+                calendar.entries.push(entry);
+            }
+        }
+        Ok(calendars)
+    }
+
+    /// Whether an entry of the calendar covers `date`
+    pub async fn excludes(id: &String, date: chrono::NaiveDate) -> anyhow::Result<bool> {
+        let (count,): (i64,) = sqlx::query_as(r#"select count(*) from calendar_entry e where e.calendar_id = ? and e.start_date <= ? and coalesce(e.end_date, e.start_date) >= ?"#)
+            .bind(id)
+            .bind(date)
+            .bind(date)
+            .fetch_one(&*POOL).observe()
+            .await?;
+        Ok(count > 0)
+    }
+}
+
+impl CalendarEntry {
+    pub async fn insert(&self) -> anyhow::Result<String> {
+        let id = nanoid::nanoid!();
+        sqlx::query(r#"insert into calendar_entry (id, calendar_id, start_date, end_date, remark) values (?, ?, ?, ?, ?)"#)
+            .bind(&id)
+            .bind(&self.calendar_id)
+            .bind(self.start_date)
+            .bind(self.end_date)
+            .bind(&self.remark)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn remove(id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"delete from calendar_entry where id = ?"#)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+}
+
+impl Secret {
+    /// Insert or replace the secret, the value is stored encrypted
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let value = secret::encrypt(&self.value)?;
+        sqlx::query(r#"insert into secret (name, value, remark) values (?, ?, ?) on duplicate key update value = values(value), remark = values(remark)"#)
+            .bind(&self.name)
+            .bind(value)
+            .bind(&self.remark)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove(name: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"delete from secret where name = ?"#)
+            .bind(name)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    /// Every secret without its value
+    pub async fn get_all_secret() -> anyhow::Result<Vec<Secret>> {
+        let secrets = sqlx::query_as(r#"select s.name,s.remark,s.updated_at from secret s order by s.name"#)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(secrets)
+    }
+
+    pub async fn get_value(name: &str) -> anyhow::Result<Option<String>> {
+        let value: Option<(String,)> = sqlx::query_as(r#"select s.value from secret s where s.name = ?"#)
+            .bind(name)
+            .fetch_optional(&*POOL).observe()
+            .await?;
+        value.map(|(value,)| secret::decrypt(&value)).transpose()
+    }
+}
+
+impl OutboxMessage {
+    pub async fn insert(
+        connection: &str,
+        message: SendMessage,
+        status: OutboxStatus,
+    ) -> anyhow::Result<String> {
+        let id = message.id.clone().unwrap_or_else(|| nanoid::nanoid!());
+        sqlx::query(r#"insert into outbox (id, connection, topic, message, status) values (?, ?, ?, ?, ?)"#)
+            .bind(&id)
+            .bind(connection)
+            .bind(message.topic.clone())
+            .bind(sqlx::types::Json(message))
+            .bind(status.as_str())
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn get_by_id(id: &str) -> anyhow::Result<Option<OutboxMessage>> {
+        let message = sqlx::query_as(r#"select o.id,o.connection,o.topic,o.message,o.status,o.attempts,o.last_error,o.created_at,o.updated_at from outbox o where o.id = ?"#)
+            .bind(id)
+            .fetch_optional(&*POOL).observe()
+            .await?;
+        Ok(message)
+    }
+
+    pub async fn count_pending(connection: &str) -> anyhow::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(r#"select count(*) from outbox o where o.connection = ? and o.status = 'pending'"#)
+            .bind(connection)
+            .fetch_one(&*POOL).observe()
+            .await?;
+        Ok(count)
+    }
+
+    /// The oldest pending messages of the connection, in the order they were queued
+    pub async fn get_pending(connection: &str, limit: i64) -> anyhow::Result<Vec<OutboxMessage>> {
+        let messages = sqlx::query_as(r#"select o.id,o.connection,o.topic,o.message,o.status,o.attempts,o.last_error,o.created_at,o.updated_at from outbox o where o.connection = ? and o.status = 'pending' order by o.seq limit ?"#)
+            .bind(connection)
+            .bind(limit)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(messages)
+    }
+
+    /// Connections with messages waiting for the broker
+    pub async fn get_pending_connections() -> anyhow::Result<Vec<String>> {
+        let connections: Vec<(String,)> = sqlx::query_as(r#"select distinct o.connection from outbox o where o.status = 'pending'"#)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(connections.into_iter().map(|(connection,)| connection).collect())
+    }
+
+    /// The latest messages, of one status when given
+    pub async fn get_all_outbox(
+        status: Option<OutboxStatus>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<OutboxMessage>> {
+        let messages = sqlx::query_as(r#"select o.id,o.connection,o.topic,o.message,o.status,o.attempts,o.last_error,o.created_at,o.updated_at from outbox o where ? is null or o.status = ? order by o.seq desc limit ?"#)
+            .bind(status.map(|status| status.as_str()))
+            .bind(status.map(|status| status.as_str()))
+            .bind(limit)
+            .fetch_all(&*POOL).observe()
+            .await?;
+        Ok(messages)
+    }
+
+    pub async fn set_status(id: &str, status: OutboxStatus) -> anyhow::Result<()> {
+        sqlx::query(r#"update outbox set status = ? where id = ?"#)
+            .bind(status.as_str())
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(())
+    }
+
+    /// Count a refused publish, returns the attempts so far
+    pub async fn record_attempt(&self, error: &str) -> anyhow::Result<u32> {
+        sqlx::query(r#"update outbox set attempts = attempts + 1, last_error = ? where id = ?"#)
+            .bind(error)
+            .bind(&self.id)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(self.attempts + 1)
+    }
+
+    /// Drop the `count` oldest pending messages of the connection, returns how many were dropped
+    pub async fn drop_oldest(connection: &str, count: i64) -> anyhow::Result<u64> {
+        let result = sqlx::query(r#"update outbox set status = 'dropped' where connection = ? and status = 'pending' order by seq limit ?"#)
+            .bind(connection)
+            .bind(count)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Queue a dropped or failed message again, in its original place
+    pub async fn retry(id: &str) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"update outbox set status = 'pending', attempts = 0 where id = ? and status in ('dropped', 'failed')"#)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        if result.rows_affected() == 0 {
+            anyhow::bail!("No dropped or failed message with id {}", id);
+        }
+        Ok(())
+    }
+
+    /// Drop a pending message, it is kept with its status until purged
+    pub async fn discard(id: &str) -> anyhow::Result<()> {
+        let result = sqlx::query(r#"update outbox set status = 'dropped' where id = ? and status = 'pending'"#)
+            .bind(id)
+            .execute(&*POOL).observe()
+            .await?;
+        if result.rows_affected() == 0 {
+            anyhow::bail!("No pending message with id {}", id);
+        }
+        Ok(())
+    }
+
+    /// Remove the messages of the connection that are no longer pending and were last touched before `before`
+    pub async fn purge_finished(
+        connection: &str,
+        before: chrono::DateTime<chrono::Local>,
+    ) -> anyhow::Result<u64> {
+        let result = sqlx::query(r#"delete from outbox where connection = ? and status != 'pending' and updated_at < ?"#)
+            .bind(connection)
+            .bind(before)
+            .execute(&*POOL).observe()
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
 impl Header {
-    pub async fn _insert(&self) -> anyhow::Result<String> {
+    pub async fn insert(&self) -> anyhow::Result<String> {
         let id = nanoid::nanoid!();
-        sqlx::query(r#"insert into header (id, topic, qos) values (?, ?, ?)"#)
+        sqlx::query(r#"insert into header (id, topic, qos, connection, default_type) values (?, ?, ?, ?, ?)"#)
             .bind(&id)
             .bind(&self.topic)
             .bind(&self.qos)
-            .execute(&*POOL)
+            .bind(&self.connection)
+            .bind(&self.default_type)
+            .execute(&*POOL).observe()
             .await?;
         Ok(id)
     }
 
-    pub async fn _remove(id: &String) -> anyhow::Result<()> {
+    /// Remove the header along with the messages received on it
+    /// `purge` deletes the stored messages of the header with it, a header that still has
+    /// messages is refused otherwise
+    pub async fn remove(id: &String, purge: bool) -> anyhow::Result<()> {
+        let mut tx = POOL.begin().await?;
+        if purge {
+            sqlx::query(r#"delete from message where header_id = ?"#)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            let (count,): (i64,) = sqlx::query_as(r#"select count(*) from message m where m.header_id = ?"#)
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+            if count > 0 {
+                anyhow::bail!("The topic has {} stored message(s), remove it with purge to delete them too", count);
+            }
+        }
         sqlx::query(r#"delete from header where id = ?"#)
             .bind(id)
-            .execute(&*POOL)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn _update(&self, id: &String) -> anyhow::Result<()> {
-        sqlx::query(r#"update header set topic = ?, qos = ? where id = ?"#)
+    pub async fn update(&self, id: &String) -> anyhow::Result<()> {
+        sqlx::query(r#"update header set topic = ?, qos = ?, connection = ?, default_type = ? where id = ?"#)
             .bind(&self.topic)
             .bind(&self.qos)
+            .bind(&self.connection)
+            .bind(&self.default_type)
             .bind(id)
-            .execute(&*POOL)
+            .execute(&*POOL).observe()
             .await?;
         Ok(())
     }
 
+    pub async fn get_by_id(id: &String) -> anyhow::Result<Option<Header>> {
+        let header = sqlx::query_as(r#"select h.id,h.topic,h.qos,h.connection,h.default_type from header h where h.id = ?"#)
+            .bind(id)
+            .fetch_optional(&*POOL).observe()
+            .await?;
+        Ok(header)
+    }
+
     pub async fn get_db_headers() -> anyhow::Result<Vec<Header>> {
-        let headers = sqlx::query_as(r#"select h.id,h.topic,h.qos from header h"#)
-            .fetch_all(&*POOL)
+        let headers = sqlx::query_as(r#"select h.id,h.topic,h.qos,h.connection,h.default_type from header h"#)
+            .fetch_all(&*POOL).observe()
             .await?;
         Ok(headers)
     }
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use linked_hash_map::LinkedHashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    message::SendMessage,
+    outbox::{OutboxMessage, OutboxStatus},
+};
+
+/// Sends remembered in memory, the oldest are forgotten first
+const MAX_TRACKED_DELIVERIES: usize = 1000;
+
+lazy_static! {
+    static ref DELIVERIES: Mutex<LinkedHashMap<String, Delivery>> =
+        Mutex::new(LinkedHashMap::new());
+    static ref DELIVERY_TX: broadcast::Sender<Delivery> = broadcast::channel(64).0;
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// waiting for the client to publish it
+    Accepted,
+    /// taken by the broker, acknowledged for QoS 1 and 2
+    Published,
+    /// in the outbox until the broker is back
+    Queued,
+    Dropped,
+    Failed,
+}
+
+/// What became of a message sent through the console
+#[derive(Serialize, Clone, Debug)]
+pub struct Delivery {
+    pub id: String,
+    pub topic: String,
+    pub connection: Option<String>,
+    pub qos: i32,
+    pub status: DeliveryStatus,
+    pub error: Option<String>,
+    pub updated_at: DateTime<Local>,
+}
+
+impl From<OutboxMessage> for Delivery {
+    fn from(entry: OutboxMessage) -> Self {
+        let status = match entry.status {
+            OutboxStatus::Pending => DeliveryStatus::Queued,
+            OutboxStatus::Sent => DeliveryStatus::Published,
+            OutboxStatus::Dropped => DeliveryStatus::Dropped,
+            OutboxStatus::Failed => DeliveryStatus::Failed,
+        };
+        Self {
+            id: entry.id,
+            topic: entry.topic,
+            connection: Some(entry.connection),
+            qos: entry.message.qos,
+            status,
+            error: entry.last_error,
+            updated_at: entry.updated_at,
+        }
+    }
+}
+
+/// Start tracking a message about to be handed to the client
+pub fn accept(id: &str, msg: &SendMessage) -> Delivery {
+    let delivery = Delivery {
+        id: id.to_string(),
+        topic: msg.topic.clone(),
+        connection: msg.connection.clone(),
+        qos: msg.qos,
+        status: DeliveryStatus::Accepted,
+        error: None,
+        updated_at: Local::now(),
+    };
+
+    let mut deliveries = DELIVERIES.lock();
+    deliveries.insert(id.to_string(), delivery.clone());
+    while deliveries.len() > MAX_TRACKED_DELIVERIES {
+        deliveries.pop_front();
+    }
+    delivery
+}
+
+/// Record what became of a tracked message, untracked ones are ignored
+pub fn update(id: &str, status: DeliveryStatus, error: Option<String>) {
+    let delivery = {
+        let mut deliveries = DELIVERIES.lock();
+        let Some(delivery) = deliveries.get_mut(id) else {
+            return;
+        };
+        delivery.status = status;
+        delivery.error = error;
+        delivery.updated_at = Local::now();
+        delivery.clone()
+    };
+    // nobody may be waiting
+    let _ = DELIVERY_TX.send(delivery);
+}
+
+/// Every change of a tracked message, as it happens
+pub fn events() -> broadcast::Receiver<Delivery> {
+    DELIVERY_TX.subscribe()
+}
+
+/// The latest known status, the outbox knows about queued messages for longer
+pub async fn get(id: &str) -> anyhow::Result<Option<Delivery>> {
+    let delivery = DELIVERIES.lock().get(id).cloned();
+    match delivery {
+        Some(delivery) if delivery.status != DeliveryStatus::Queued => Ok(Some(delivery)),
+        delivery => match OutboxMessage::get_by_id(id).await? {
+            Some(entry) => Ok(Some(entry.into())),
+            None => Ok(delivery),
+        },
+    }
+}
+
+/// Wait until the client has done with the message or `timeout` runs out,
+/// `events` must be subscribed before the message is sent
+pub async fn settled(
+    id: &str,
+    mut events: broadcast::Receiver<Delivery>,
+    timeout: Duration,
+) -> anyhow::Result<Option<Delivery>> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(delivery)) => {
+                if delivery.id == id && delivery.status != DeliveryStatus::Accepted {
+                    return Ok(Some(delivery));
+                }
+            }
+            Ok(Err(RecvError::Lagged(_))) => {
+                let delivery = get(id).await?;
+                if delivery
+                    .as_ref()
+                    .is_some_and(|delivery| delivery.status != DeliveryStatus::Accepted)
+                {
+                    return Ok(delivery);
+                }
+            }
+            Ok(Err(RecvError::Closed)) | Err(_) => return get(id).await,
+        }
+    }
+}
@@ -1,10 +1,23 @@
 use serde::{Deserialize, Serialize};
 
+use crate::config::DEFAULT_CONNECTION;
+
 #[derive(Deserialize, Serialize, Clone, Default, Debug, sqlx::FromRow)]
 pub struct Header {
+    #[serde(default)]
     pub id: String,
     pub topic: String,
     pub qos: i32,
+    /// name of the broker connection the topic is subscribed on
+    #[serde(default = "default_connection")]
+    pub connection: String,
+    /// content type of the messages published without one
+    #[serde(default)]
+    pub default_type: Option<String>,
+}
+
+fn default_connection() -> String {
+    DEFAULT_CONNECTION.to_string()
 }
 
 impl Header {
@@ -36,7 +49,7 @@ impl Header {
         true
     }
 
-    pub fn _check(&self) -> anyhow::Result<()> {
+    pub fn check(&self) -> anyhow::Result<()> {
         if self.topic.is_empty() {
             anyhow::bail!("topic is empty");
         }
@@ -49,16 +62,76 @@ impl Header {
             anyhow::bail!("system/# is a reserved topic");
         }
 
+        if let Some(default_type) = &self.default_type {
+            default_type
+                .parse::<mime::Mime>()
+                .map_err(|_| anyhow::anyhow!("default_type is not a content type"))?;
+        }
+
+        let known = crate::tyme_config
+            .lock()
+            .connections()
+            .iter()
+            .any(|broker| broker.name == self.connection);
+        if !known {
+            anyhow::bail!("Unknown broker connection: {}", self.connection);
+        }
+
+        Ok(())
+    }
+
+    /// No other header subscribes to the same topic
+    pub async fn check_unique(&self) -> anyhow::Result<()> {
+        let duplicate = Self::get_db_headers().await?.into_iter().any(|header| {
+            header.topic == self.topic
+                && header.connection == self.connection
+                && header.id != self.id
+        });
+        if duplicate {
+            anyhow::bail!("{} is already subscribed", self.topic);
+        }
         Ok(())
     }
 
     pub async fn get_all_header() -> anyhow::Result<Vec<Header>> {
         let mut headers = Self::get_db_headers().await?;
-        headers.push(Header {
+        headers.push(Self::system(DEFAULT_CONNECTION));
+        Ok(headers)
+    }
+
+    /// The headers subscribed on one broker connection, each has its own `system/#`
+    pub async fn get_connection_headers(connection: &str) -> anyhow::Result<Vec<Header>> {
+        let mut headers = Self::get_db_headers()
+            .await?
+            .into_iter()
+            .filter(|header| header.connection == connection)
+            .collect::<Vec<_>>();
+        headers.push(Self::system(connection));
+        Ok(headers)
+    }
+
+    /// The first header of the connection whose topic filter matches `topic`
+    pub async fn find(connection: &str, topic: &str) -> anyhow::Result<Option<Header>> {
+        Ok(Self::get_connection_headers(connection)
+            .await?
+            .into_iter()
+            .find(|header| header.mqtt_topic_matches(topic)))
+    }
+
+    fn system(connection: &str) -> Header {
+        Header {
             id: String::from(""),
             topic: "system/#".to_string(),
             qos: 2,
-        });
-        Ok(headers)
+            connection: connection.to_string(),
+            default_type: None,
+        }
     }
 }
+
+/// Changes to the broker subscriptions, applied by the MQTT client
+#[derive(Clone, Debug)]
+pub enum HeaderCommand {
+    Subscribe(Header),
+    Unsubscribe(Header),
+}
@@ -11,12 +11,21 @@ extern crate serde_json;
 extern crate sqlx;
 
 mod args;
+mod calendar;
+mod command;
 mod config;
 mod db;
+mod delivery;
 mod header;
 mod message;
+mod metrics;
 mod mqtt;
+mod outbox;
+mod script;
+mod secret;
+mod sysinfo;
 mod task;
+mod trigger;
 mod web_console;
 
 pub use args::START_PARAM as start_param;
@@ -25,8 +34,35 @@ pub use task::TaskManager;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    secret::init();
+
     if start_param.init {
         TymeConfig::initial().unwrap();
+    } else if let Some(args::Command::Test { filter }) = &start_param.cmd {
+        let reports = script::test_runner::run(filter.clone()).await?;
+        let mut failed = 0;
+
+        for report in &reports {
+            if let Some(err) = &report.error {
+                failed += 1;
+                println!("FAIL {}: {}", report.file, err);
+            }
+            for test in &report.tests {
+                match &test.error {
+                    None => println!("ok   {}::{}", report.file, test.name),
+                    Some(err) => {
+                        failed += 1;
+                        println!("FAIL {}::{}: {}", report.file, test.name, err);
+                    }
+                }
+            }
+        }
+
+        let total = reports.iter().map(|r| r.tests.len()).sum::<usize>();
+        println!("{} tests, {} failed", total, failed);
+        if failed > 0 {
+            std::process::exit(1);
+        }
     } else {
         log_init()?;
 
@@ -38,12 +74,12 @@ async fn main() -> anyhow::Result<()> {
             tokio::sync::mpsc::unbounded_channel::<message::SendMessage>();
 
         let (sub_header_tx, sub_header_rx) =
-            tokio::sync::mpsc::unbounded_channel::<header::Header>();
+            tokio::sync::mpsc::unbounded_channel::<header::HeaderCommand>();
 
         let (rec_msg_tx, _) =
             tokio::sync::broadcast::channel::<(header::Header, message::RecMessage)>(16);
 
-        let task_manager = TaskManager::new(send_msg_tx.clone());
+        let task_manager = TaskManager::new(send_msg_tx.clone(), rec_msg_tx.clone());
 
         db::db_init().await?;
 
@@ -1,11 +1,10 @@
 use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::{DateTime, Local};
 use paho_mqtt::{self as mqtt};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-use crate::header::Header;
-
 #[derive(Deserialize, Serialize, Clone, Debug, FromRow)]
 pub struct RecMessage {
     pub id: String,
@@ -18,6 +17,12 @@ pub struct RecMessage {
     pub content: MessageContent,
     pub sender: Option<String>,
     pub receiver: Option<String>,
+    /// published by a client other than tyme, without its user properties
+    #[serde(default)]
+    pub external: bool,
+    /// the stored copy is purged after this time
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Local>>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -30,6 +35,21 @@ pub struct SendMessage {
     #[serde(rename = "type")]
     pub message_type: String,
     pub raw: String,
+    /// how `raw` holds the payload
+    #[serde(default)]
+    pub encoding: PayloadEncoding,
+    /// name of the broker connection to publish on, the default one when `None`
+    #[serde(default)]
+    pub connection: Option<String>,
+    /// set when the delivery is tracked, also the id of its outbox entry
+    #[serde(default)]
+    pub id: Option<String>,
+    /// seconds the broker keeps the message for subscribers, sent as the MQTT 5 message expiry interval
+    #[serde(default)]
+    pub expiry_interval: Option<u32>,
+    /// seconds the receivers keep their stored copy
+    #[serde(default)]
+    pub ttl: Option<u32>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, sqlx::FromRow)]
@@ -39,6 +59,61 @@ pub struct MessageContent {
     pub message_type: String,
     pub raw: String,
     pub html: Option<String>,
+    /// how `raw` holds the payload
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub encoding: PayloadEncoding,
+}
+
+/// Text payloads are kept as is, anything else as base64
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+impl PayloadEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadEncoding::Utf8 => "utf8",
+            PayloadEncoding::Base64 => "base64",
+        }
+    }
+
+    pub fn encode(&self, payload: &[u8]) -> String {
+        match self {
+            PayloadEncoding::Utf8 => String::from_utf8_lossy(payload).to_string(),
+            PayloadEncoding::Base64 => STANDARD.encode(payload),
+        }
+    }
+
+    pub fn decode(&self, raw: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            PayloadEncoding::Utf8 => Ok(raw.as_bytes().to_vec()),
+            PayloadEncoding::Base64 => Ok(STANDARD.decode(raw)?),
+        }
+    }
+}
+
+impl TryFrom<String> for PayloadEncoding {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "utf8" => Ok(PayloadEncoding::Utf8),
+            "base64" => Ok(PayloadEncoding::Base64),
+            _ => Err(anyhow::anyhow!("Unknown payload encoding: {}", value)),
+        }
+    }
+}
+
+impl MessageContent {
+    /// The payload as it was published
+    pub fn payload(&self) -> anyhow::Result<Vec<u8>> {
+        self.encoding.decode(&self.raw)
+    }
 }
 
 impl SendMessage {
@@ -59,6 +134,21 @@ impl SendMessage {
             )?;
         }
 
+        if self.ephemeral {
+            props.push_string_pair(mqtt::PropertyCode::UserProperty, "ephemeral", "true")?;
+        }
+
+        if let Some(ttl) = self.ttl {
+            props.push_string_pair(mqtt::PropertyCode::UserProperty, "ttl", &ttl.to_string())?;
+        }
+
+        if let Some(expiry_interval) = self.expiry_interval {
+            props.push_int(
+                mqtt::PropertyCode::MessageExpiryInterval,
+                i32::try_from(expiry_interval).context("expiry_interval is too large")?,
+            )?;
+        }
+
         props.push_string(
             mqtt::PropertyCode::ContentType,
             self.message_type.clone().as_str(),
@@ -66,7 +156,7 @@ impl SendMessage {
 
         let msg = mqtt::MessageBuilder::new()
             .topic(self.topic.clone())
-            .payload(self.raw.clone())
+            .payload(self.encoding.decode(&self.raw)?)
             .properties(props)
             .qos(self.qos)
             .retained(self.retain.unwrap_or(false))
@@ -78,8 +168,12 @@ impl SendMessage {
 
 impl RecMessage {
     pub fn to_html(&mut self) -> anyhow::Result<()> {
-        let msg_type = self.content.message_type.clone();
-        let msg_type: mime::Mime = msg_type.parse().context("Unable to parse mime type")?;
+        // a content type that does not parse is rendered like any unknown one
+        let msg_type: mime::Mime = self
+            .content
+            .message_type
+            .parse()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
 
         if msg_type.essence_str().eq("text/markdown") {
             let html: String =
@@ -93,24 +187,36 @@ impl RecMessage {
             )
             .unwrap();
             self.content.html = Some(html);
+        } else if msg_type.type_() == mime::IMAGE {
+            let data = match self.content.encoding {
+                PayloadEncoding::Base64 => self.content.raw.clone(),
+                PayloadEncoding::Utf8 => STANDARD.encode(&self.content.raw),
+            };
+            self.content.html = Some(format!(
+                r#"<img src="data:{};base64,{}" alt="{}">"#,
+                escape_html(msg_type.essence_str()),
+                data,
+                escape_html(&self.topic)
+            ));
+        } else if self.content.encoding == PayloadEncoding::Base64 {
+            // binary payloads of any other type are only offered for download
+            self.content.html = Some(format!(
+                r#"<p>{}, {} bytes <a href="/c/msg-raw/{}" download>download</a></p>"#,
+                escape_html(&self.content.message_type),
+                self.content.payload()?.len(),
+                escape_html(&self.id)
+            ));
         } else {
-            Err(anyhow::anyhow!("Unsupported message type"))?;
+            self.content.html = Some(format!("<pre>{}</pre>", escape_html(&self.content.raw)));
         };
         Ok(())
     }
 
-    pub async fn get_header(&self) -> anyhow::Result<Option<Header>> {
-        Ok(Header::get_all_header()
-            .await?
-            .into_iter()
-            .find(|pattern| pattern.mqtt_topic_matches(self.topic.as_ref())))
-    }
-}
-
-impl TryFrom<&mqtt::Message> for RecMessage {
-    type Error = anyhow::Error;
-
-    fn try_from(msg: &mqtt::Message) -> Result<Self, Self::Error> {
+    /// Read a message of any publisher.
+    ///
+    /// Messages without the `sender` property are external, messages without a content type
+    /// get `default_type` or a type guessed from the payload
+    pub fn from_mqtt(msg: &mqtt::Message, default_type: Option<&str>) -> Self {
         let topic = msg.topic().to_string();
 
         let qos = msg.qos();
@@ -120,24 +226,37 @@ impl TryFrom<&mqtt::Message> for RecMessage {
         let sender = msg.properties().find_user_property("sender");
 
         let mine = sender
-            .clone()
-            .context("Unable to find publish property")?
-            .eq(&crate::config::TYME_CONFIG.lock().clone().get_clint_name());
+            .as_ref()
+            .is_some_and(|sender| *sender == crate::config::TYME_CONFIG.lock().get_clint_name());
 
         let receiver = msg.properties().find_user_property("receiver");
 
-        let message_type = msg
+        let expires_at = msg
             .properties()
-            .get_string(mqtt::PropertyCode::ContentType)
-            .context("Unable to find content type property")?;
+            .find_user_property("ttl")
+            .and_then(|ttl| ttl.parse::<u32>().ok())
+            .map(|ttl| Local::now() + chrono::Duration::seconds(ttl as i64));
+
+        let message_type = match msg.properties().get_string(mqtt::PropertyCode::ContentType) {
+            Some(message_type) => message_type,
+            None => match default_type {
+                Some(default_type) => default_type.to_string(),
+                None => sniff_type(msg.payload()).to_string(),
+            },
+        };
 
+        let encoding = match std::str::from_utf8(msg.payload()) {
+            Ok(_) if !is_binary_type(&message_type) => PayloadEncoding::Utf8,
+            _ => PayloadEncoding::Base64,
+        };
         let content = MessageContent {
             message_type,
-            raw: msg.payload_str().to_string(),
+            raw: encoding.encode(msg.payload()),
             html: None,
+            encoding,
         };
 
-        Ok(RecMessage {
+        RecMessage {
             id: nanoid::nanoid!(),
             topic,
             qos,
@@ -145,8 +264,108 @@ impl TryFrom<&mqtt::Message> for RecMessage {
             mine,
             timestamp: Local::now().into(),
             content,
+            external: sender.is_none(),
             sender,
             receiver,
-        })
+            expires_at,
+        }
+    }
+}
+
+/// Content type of a payload that came without one
+fn sniff_type(payload: &[u8]) -> &'static str {
+    if payload.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if payload.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if payload.starts_with(b"GIF8") {
+        "image/gif"
+    } else if std::str::from_utf8(payload).is_err() {
+        "application/octet-stream"
+    } else if serde_json::from_slice::<serde_json::Value>(payload).is_ok() {
+        "application/json"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Content types stored as base64 even when the payload happens to be valid UTF-8
+fn is_binary_type(message_type: &str) -> bool {
+    let Ok(message_type) = message_type.parse::<mime::Mime>() else {
+        return false;
+    };
+    message_type.type_() == mime::IMAGE
+        || message_type.type_() == mime::AUDIO
+        || message_type.type_() == mime::VIDEO
+        || message_type.essence_str() == "application/octet-stream"
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffed_types() {
+        assert_eq!(sniff_type(br#"{"temperature": 21.5}"#), "application/json");
+        assert_eq!(sniff_type(b"[1, 2, 3]"), "application/json");
+        assert_eq!(sniff_type(b"door opened"), "text/plain");
+        assert_eq!(sniff_type(b""), "text/plain");
+        assert_eq!(sniff_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_type(&[0xff, 0xd8, 0xff, 0xe0]), "image/jpeg");
+        assert_eq!(sniff_type(b"GIF89a"), "image/gif");
+        assert_eq!(
+            sniff_type(&[0x00, 0xc3, 0x28, 0xff]),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn binary_types() {
+        assert!(is_binary_type("image/png"));
+        assert!(is_binary_type("audio/ogg"));
+        assert!(is_binary_type("video/mp4"));
+        assert!(is_binary_type("application/octet-stream"));
+        assert!(!is_binary_type("application/json"));
+        assert!(!is_binary_type("text/plain; charset=utf-8"));
+        assert!(!is_binary_type("not a type"));
+    }
+
+    #[test]
+    fn binary_payload_round_trips_through_base64() {
+        let payload = [0x89, 0x00, 0xff, 0xfe, 0x10, 0x80];
+        let raw = PayloadEncoding::Base64.encode(&payload);
+        assert_eq!(raw, "iQD//hCA");
+        assert_eq!(PayloadEncoding::Base64.decode(&raw).unwrap(), payload);
+
+        let content = MessageContent {
+            message_type: "application/octet-stream".to_string(),
+            raw,
+            html: None,
+            encoding: PayloadEncoding::Base64,
+        };
+        assert_eq!(content.payload().unwrap(), payload);
+
+        assert!(PayloadEncoding::Base64.decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn encoding_names() {
+        for encoding in [PayloadEncoding::Utf8, PayloadEncoding::Base64] {
+            let parsed = PayloadEncoding::try_from(encoding.as_str().to_string()).unwrap();
+            assert_eq!(parsed, encoding);
+        }
+        assert!(PayloadEncoding::try_from("hex".to_string()).is_err());
+        assert_eq!(
+            PayloadEncoding::Utf8.decode("héllo").unwrap(),
+            "héllo".as_bytes()
+        );
     }
 }
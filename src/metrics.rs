@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::task::{Task, TaskRun};
+
+lazy_static! {
+    static ref TASK_RUNS: IntCounterVec = register_int_counter_vec!(
+        "tyme_task_runs_total",
+        "Executions of a task",
+        &["task_id", "task"]
+    )
+    .unwrap();
+    static ref TASK_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "tyme_task_failures_total",
+        "Failed executions of a task",
+        &["task_id", "task"]
+    )
+    .unwrap();
+    static ref TASK_DURATION: HistogramVec = register_histogram_vec!(
+        "tyme_task_duration_seconds",
+        "Duration of a task execution",
+        &["task_id", "task"]
+    )
+    .unwrap();
+    static ref TASK_LAST_SUCCESS: IntGaugeVec = register_int_gauge_vec!(
+        "tyme_task_last_success_timestamp_seconds",
+        "Unix time of the last successful execution of a task",
+        &["task_id", "task"]
+    )
+    .unwrap();
+    static ref MQTT_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "tyme_mqtt_messages_received_total",
+        "Messages received, by the header they matched",
+        &["connection", "header"]
+    )
+    .unwrap();
+    static ref MQTT_SENT: IntCounterVec = register_int_counter_vec!(
+        "tyme_mqtt_messages_sent_total",
+        "Messages published, by the header matching their topic",
+        &["connection", "header"]
+    )
+    .unwrap();
+    static ref MQTT_RECONNECTS: IntCounterVec = register_int_counter_vec!(
+        "tyme_mqtt_reconnects_total",
+        "Reconnections to the broker after a lost connection",
+        &["connection"]
+    )
+    .unwrap();
+    static ref MQTT_PUBLISH_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "tyme_mqtt_publish_errors_total",
+        "Messages the broker did not accept",
+        &["connection"]
+    )
+    .unwrap();
+    static ref DB_CONNECTIONS: IntGauge =
+        register_int_gauge!("tyme_db_connections", "Open database connections").unwrap();
+    static ref DB_CONNECTIONS_IDLE: IntGauge =
+        register_int_gauge!("tyme_db_connections_idle", "Idle database connections").unwrap();
+    static ref DB_QUERY_DURATION: Histogram = register_histogram!(
+        "tyme_db_query_duration_seconds",
+        "Duration of a database query"
+    )
+    .unwrap();
+    static ref DB_QUERY_ERRORS: IntCounter =
+        register_int_counter!("tyme_db_query_errors_total", "Database queries that failed")
+            .unwrap();
+}
+
+pub fn task_run(task: &Task, run: &TaskRun) {
+    let labels = [task.id.as_str(), task.name.as_str()];
+
+    TASK_RUNS.with_label_values(&labels).inc();
+    TASK_DURATION
+        .with_label_values(&labels)
+        .observe((run.finished_at - run.started_at).num_milliseconds() as f64 / 1000.0);
+
+    if run.success {
+        TASK_LAST_SUCCESS
+            .with_label_values(&labels)
+            .set(run.finished_at.timestamp());
+    } else {
+        TASK_FAILURES.with_label_values(&labels).inc();
+    }
+}
+
+/// Drop the series of a removed task
+pub fn remove_task(task: &Task) {
+    let labels = [task.id.as_str(), task.name.as_str()];
+
+    let _ = TASK_RUNS.remove_label_values(&labels);
+    let _ = TASK_FAILURES.remove_label_values(&labels);
+    let _ = TASK_DURATION.remove_label_values(&labels);
+    let _ = TASK_LAST_SUCCESS.remove_label_values(&labels);
+}
+
+/// `header` is the topic filter of the header, empty when no header matched
+pub fn message_received(connection: &str, header: &str) {
+    MQTT_RECEIVED.with_label_values(&[connection, header]).inc();
+}
+
+pub fn message_sent(connection: &str, header: &str) {
+    MQTT_SENT.with_label_values(&[connection, header]).inc();
+}
+
+pub fn reconnected(connection: &str) {
+    MQTT_RECONNECTS.with_label_values(&[connection]).inc();
+}
+
+pub fn publish_error(connection: &str) {
+    MQTT_PUBLISH_ERRORS.with_label_values(&[connection]).inc();
+}
+
+pub fn db_query(duration: Duration, success: bool) {
+    DB_QUERY_DURATION.observe(duration.as_secs_f64());
+    if !success {
+        DB_QUERY_ERRORS.inc();
+    }
+}
+
+/// Render every metric in the Prometheus text format
+pub fn gather() -> anyhow::Result<String> {
+    let (size, idle) = crate::db::pool_status();
+    DB_CONNECTIONS.set(size as i64);
+    DB_CONNECTIONS_IDLE.set(idle as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -1,63 +1,632 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
 
 use anyhow::Context;
+use chrono::{DateTime, Local};
 use futures::StreamExt;
 use log::{error, info};
 use mqtt::AsyncReceiver;
-use tokio::sync::{broadcast, mpsc::UnboundedReceiver};
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
 
 use paho_mqtt::{self as mqtt, AsyncClient};
+use rand::Rng;
 
 use crate::{
-    config::TymeConfig,
-    header::Header,
-    message::{RecMessage, SendMessage},
+    config::{BrokerConfig, MQTTConfig, Reconnect, TymeConfig, DEFAULT_CONNECTION},
+    delivery::{self, DeliveryStatus},
+    header::{Header, HeaderCommand},
+    message::{PayloadEncoding, RecMessage, SendMessage},
+    outbox::{self, OutboxMessage, OutboxStatus},
     tyme_config,
 };
 
+/// How often queued messages are retried when no reconnect triggers it
+const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const OUTBOX_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// How often stored messages past their TTL are removed
+const MESSAGE_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Messages handed from the outbox at once, the next batch waits for their acknowledgements
+const OUTBOX_BATCH: i64 = 100;
+/// A publish not acknowledged by then counts as failed
+const PUBLISH_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+/// A queued message the broker refused this many times while connected is marked failed
+const OUTBOX_MAX_ATTEMPTS: u32 = 5;
+
+lazy_static! {
+    static ref STATUS: Mutex<Vec<MqttStatus>> = Mutex::new(Vec::new());
+    static ref STATUS_TX: broadcast::Sender<MqttStatus> = broadcast::channel(16).0;
+    /// Subscribed headers by connection, labels the metrics of sent messages without a query
+    static ref HEADERS: Mutex<HashMap<String, Vec<Header>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    Reconnecting,
+}
+
+/// Connection to a broker, as shown by the console
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MqttStatus {
+    /// name of the broker connection
+    pub name: String,
+    pub state: ConnectionState,
+    pub broker: String,
+    /// negotiated with the broker, `None` until connected
+    pub mqtt_version: Option<i32>,
+    pub connected_since: Option<DateTime<Local>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Local>>,
+    /// failed attempts since the connection was lost
+    pub reconnect_attempts: u32,
+}
+
+/// The status of every broker connection, in configuration order
+pub fn status() -> Vec<MqttStatus> {
+    STATUS.lock().clone()
+}
+
+/// Every change of a connection status, as it happens
+pub fn status_events() -> broadcast::Receiver<MqttStatus> {
+    STATUS_TX.subscribe()
+}
+
+fn update_status(name: &str, update: impl FnOnce(&mut MqttStatus)) {
+    let status = {
+        let mut statuses = STATUS.lock();
+        let index = match statuses.iter().position(|status| status.name == name) {
+            Some(index) => index,
+            None => {
+                statuses.push(MqttStatus {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+                statuses.len() - 1
+            }
+        };
+        update(&mut statuses[index]);
+        statuses[index].clone()
+    };
+    // nobody may be listening
+    let _ = STATUS_TX.send(status);
+}
+
+fn status_error(name: &str, err: &dyn std::fmt::Display) {
+    update_status(name, |status| {
+        status.last_error = Some(err.to_string());
+        status.last_error_at = Some(Local::now());
+    });
+}
+
+fn status_connected(name: &str, rsp: &mqtt::ServerResponse) {
+    update_status(name, |status| {
+        status.state = ConnectionState::Connected;
+        status.connected_since = Some(Local::now());
+        status.reconnect_attempts = 0;
+        if let Some(conn_rsp) = rsp.connect_response() {
+            status.broker = conn_rsp.server_uri;
+            status.mqtt_version = Some(conn_rsp.mqtt_version);
+        }
+    });
+}
+
 pub async fn run_mqtt_clint(
     mut send_msg_rx: UnboundedReceiver<SendMessage>,
-    sub_header_tx: UnboundedReceiver<Header>,
+    sub_header_rx: UnboundedReceiver<HeaderCommand>,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
     task_manager: crate::TaskManager,
 ) -> anyhow::Result<()> {
     let config = tyme_config.lock().clone();
-    let mut clint = get_mqtt_clint(&config)?;
 
-    let sub_clint = clint.clone();
-    let strm = clint.get_stream(None);
-    tokio::spawn(subscribe(strm, sub_clint, rec_msg_tx));
-
-    let header_clint = clint.clone();
-    tokio::spawn(subscribe_topic(header_clint, sub_header_tx));
+    let mut clints = HashMap::new();
+    for broker in config.connections() {
+        let clint = start_connection(&broker, rec_msg_tx.clone()).await?;
+        clints.insert(broker.name, clint);
+    }
 
-    let conn_opts = get_conn_option(&config)?;
-    connect(&clint, conn_opts).await?;
+    tokio::spawn(subscribe_topic(clints.clone(), sub_header_rx));
 
     task_manager.start().await?;
 
-    while let Some(send_msg) = send_msg_rx.recv().await {
-        let msg = send_msg.to_mqtt()?;
-        clint.publish(msg).await?;
+    let (acked_tx, mut acked_rx) = mpsc::unbounded_channel();
+    let mut publisher = Publisher {
+        clints,
+        backlog: HashSet::new(),
+        in_flight: HashMap::new(),
+        direct: HashMap::new(),
+        held: HashMap::new(),
+        next_direct: 0,
+        acked_tx,
+    };
+    let mut status_rx = status_events();
+    let mut retry = tokio::time::interval(OUTBOX_RETRY_INTERVAL);
+    let mut purge = tokio::time::interval(OUTBOX_PURGE_INTERVAL);
+    let mut expire = tokio::time::interval(MESSAGE_EXPIRY_INTERVAL);
+
+    loop {
+        tokio::select! {
+            send_msg = send_msg_rx.recv() => {
+                let Some(send_msg) = send_msg else { break };
+                publisher.send(send_msg).await;
+            }
+            Some(acked) = acked_rx.recv() => publisher.acked(acked).await,
+            // a reconnect is the moment to drain, the interval covers the rest
+            _ = status_rx.recv() => publisher.flush_outbox().await,
+            _ = retry.tick() => {
+                match OutboxMessage::get_pending_connections().await {
+                    Ok(connections) => publisher.backlog.extend(connections),
+                    Err(err) => error!("Error reading the outbox: {}", err),
+                }
+                publisher.flush_outbox().await;
+            }
+            _ = purge.tick() => purge_outbox(&config).await,
+            _ = expire.tick() => {
+                match RecMessage::purge_expired().await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Purged {} expired message(s)", count),
+                    Err(err) => error!("Error purging expired messages: {}", err),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Hands messages to the clients without waiting for the broker,
+/// acknowledgements come back through `acked_tx`
+struct Publisher {
+    clints: HashMap<String, AsyncClient>,
+    /// connections whose messages go through the outbox until it is drained
+    backlog: HashSet<String>,
+    /// outbox messages awaiting their acknowledgement, by connection
+    in_flight: HashMap<String, HashSet<String>>,
+    /// messages published directly, by connection, in the order they were sent
+    direct: HashMap<String, VecDeque<Direct>>,
+    /// messages sent in backlog mode while direct publishes were still unresolved,
+    /// they are queued once the failed ones are
+    held: HashMap<String, Vec<SendMessage>>,
+    next_direct: u64,
+    acked_tx: UnboundedSender<Acked>,
+}
+
+/// A message published without going through the outbox, queued when the broker fails to take it
+struct Direct {
+    seq: u64,
+    send_msg: SendMessage,
+    /// `None` until acknowledged or failed
+    success: Option<bool>,
+}
+
+/// The end of a publish, acknowledged or not
+struct Acked {
+    connection: String,
+    publish: Publish,
+    success: bool,
+}
+
+enum Publish {
+    Direct(u64),
+    Outbox(String),
+}
+
+impl Publisher {
+    /// Publish the message, or queue it while the broker is unreachable or older messages wait
+    async fn send(&mut self, send_msg: SendMessage) {
+        let id = send_msg.id.clone();
+        let name = send_msg
+            .connection
+            .clone()
+            .unwrap_or(DEFAULT_CONNECTION.to_string());
+        let Some(clint) = self.clints.get(&name) else {
+            error!(
+                "Unknown broker connection {}, message to {} dropped",
+                name, send_msg.topic
+            );
+            track(
+                &id,
+                DeliveryStatus::Failed,
+                Some(format!("Unknown broker connection {}", name)),
+            );
+            return;
+        };
+
+        // a message the client cannot build is not worth queueing
+        let msg = match send_msg.to_mqtt() {
+            Ok(msg) => msg,
+            Err(err) => {
+                error!("Invalid message to {}, dropped: {}", send_msg.topic, err);
+                track(&id, DeliveryStatus::Failed, Some(err.to_string()));
+                return;
+            }
+        };
+
+        if self.backlog.contains(&name) || !clint.is_connected() {
+            // a direct publish sent before may still fail and has to be queued first
+            if self
+                .direct
+                .get(&name)
+                .is_some_and(|direct| !direct.is_empty())
+            {
+                self.held.entry(name.clone()).or_default().push(send_msg);
+                self.backlog.insert(name);
+            } else if queue(&name, send_msg).await {
+                self.backlog.insert(name);
+            }
+            return;
+        }
+
+        let seq = self.next_direct;
+        self.next_direct += 1;
+        let token = clint.publish(msg);
+        let acked_tx = self.acked_tx.clone();
+        let topic = send_msg.topic.clone();
+        self.direct
+            .entry(name.clone())
+            .or_default()
+            .push_back(Direct {
+                seq,
+                send_msg,
+                success: None,
+            });
+        tokio::spawn(async move {
+            let success = match acknowledged(token, &name, &topic).await {
+                Ok(()) => {
+                    track(&id, DeliveryStatus::Published, None);
+                    true
+                }
+                Err(err) => {
+                    error!("Error publishing to {}: {}", topic, err);
+                    false
+                }
+            };
+            let _ = acked_tx.send(Acked {
+                connection: name,
+                publish: Publish::Direct(seq),
+                success,
+            });
+        });
+    }
+
+    async fn acked(&mut self, acked: Acked) {
+        if !acked.success {
+            self.backlog.insert(acked.connection.clone());
+        }
+
+        match acked.publish {
+            Publish::Direct(seq) => {
+                let direct = self.direct.entry(acked.connection.clone()).or_default();
+                if let Some(entry) = direct.iter_mut().find(|entry| entry.seq == seq) {
+                    entry.success = Some(acked.success);
+                }
+                self.settle(&acked.connection).await;
+            }
+            Publish::Outbox(outbox_id) => {
+                let in_flight = self.in_flight.entry(acked.connection.clone()).or_default();
+                in_flight.remove(&outbox_id);
+                // the whole batch is done, the next one can go
+                if in_flight.is_empty() {
+                    self.flush(&acked.connection).await;
+                }
+            }
+        }
+    }
+
+    /// Queue the failed direct publishes whose predecessors are all resolved, in the order they
+    /// were sent, and the held messages once none is left
+    async fn settle(&mut self, name: &str) {
+        let mut failed = Vec::new();
+        let direct = self.direct.entry(name.to_string()).or_default();
+        while let Some(success) = direct.front().and_then(|entry| entry.success) {
+            let entry = direct.pop_front().unwrap();
+            if !success {
+                failed.push(entry.send_msg);
+            }
+        }
+        let settled = direct.is_empty();
+        if settled {
+            failed.extend(self.held.remove(name).unwrap_or_default());
+        }
+
+        for send_msg in failed {
+            queue(name, send_msg).await;
+        }
+        if settled && self.backlog.contains(name) {
+            self.flush(name).await;
+        }
+    }
+
+    /// Hand the queued messages of every connected broker to its client
+    async fn flush_outbox(&mut self) {
+        for name in self.backlog.clone() {
+            self.flush(&name).await;
+        }
+    }
+
+    async fn flush(&mut self, name: &str) {
+        let Some(clint) = self.clints.get(name) else {
+            error!(
+                "Unknown broker connection {}, its queued messages are kept",
+                name
+            );
+            self.backlog.remove(name);
+            return;
+        };
+        // draining now would end the backlog before the failed ones are queued
+        let unresolved = self
+            .direct
+            .get(name)
+            .is_some_and(|direct| !direct.is_empty());
+        if !clint.is_connected() || unresolved {
+            return;
+        }
+
+        let in_flight = self.in_flight.entry(name.to_string()).or_default();
+        match drain(clint, name, in_flight, &self.acked_tx).await {
+            Ok(true) => {
+                if self.backlog.remove(name) {
+                    info!("Outbox of {} drained.", name);
+                }
+            }
+            Ok(false) => {}
+            Err(err) => error!("Error draining the outbox of {}: {}", name, err),
+        }
+    }
+}
+
+fn track(id: &Option<String>, status: DeliveryStatus, error: Option<String>) {
+    if let Some(id) = id {
+        delivery::update(id, status, error);
+    }
+}
+
+/// Put the message in the outbox of the connection, returns whether it waits there
+async fn queue(name: &str, send_msg: SendMessage) -> bool {
+    let id = send_msg.id.clone();
+    let config = tyme_config.lock().clone();
+    let outbox = config
+        .connections()
+        .into_iter()
+        .find(|broker| broker.name == name)
+        .map(|broker| broker.mqtt_config.outbox)
+        .unwrap_or_default();
+    let topic = send_msg.topic.clone();
+    match outbox::enqueue(name, send_msg, &outbox).await {
+        Ok(OutboxStatus::Dropped) => {
+            track(
+                &id,
+                DeliveryStatus::Dropped,
+                Some("The outbox is full".to_string()),
+            );
+            false
+        }
+        Ok(_) => {
+            track(&id, DeliveryStatus::Queued, None);
+            true
+        }
+        Err(err) => {
+            error!("Error queueing message to {}, dropped: {}", topic, err);
+            track(&id, DeliveryStatus::Failed, Some(err.to_string()));
+            false
+        }
+    }
+}
+
+/// Wait for the broker to take a handed message, acknowledged for QoS 1 and 2
+async fn acknowledged(token: mqtt::DeliveryToken, name: &str, topic: &str) -> anyhow::Result<()> {
+    let result = match tokio::time::timeout(PUBLISH_ACK_TIMEOUT, token).await {
+        Ok(result) => result.map_err(anyhow::Error::from),
+        Err(_) => Err(anyhow::anyhow!("No acknowledgement from the broker")),
+    };
+    if let Err(err) = result {
+        crate::metrics::publish_error(name);
+        status_error(name, &err);
+        return Err(err);
+    }
+
+    crate::metrics::message_sent(name, &header_label(name, topic));
+    Ok(())
+}
+
+/// Hand the next batch of pending messages to the client once the previous one is acknowledged,
+/// returns whether the outbox is empty
+async fn drain(
+    clint: &AsyncClient,
+    name: &str,
+    in_flight: &mut HashSet<String>,
+    acked_tx: &UnboundedSender<Acked>,
+) -> anyhow::Result<bool> {
+    if !in_flight.is_empty() {
+        return Ok(false);
+    }
+    let pending = OutboxMessage::get_pending(name, OUTBOX_BATCH).await?;
+    if pending.is_empty() {
+        return Ok(true);
+    }
+
+    for entry in pending {
+        // the connection dropped again, the rest waits for the next one
+        if !clint.is_connected() {
+            break;
+        }
+
+        // the expiry interval runs from the send, not from leaving the outbox
+        let mut send_msg = entry.message.0.clone();
+        if let Some(expiry_interval) = send_msg.expiry_interval {
+            let waited = (Local::now() - entry.created_at).num_seconds().max(0) as u32;
+            if waited >= expiry_interval {
+                OutboxMessage::set_status(&entry.id, OutboxStatus::Dropped).await?;
+                delivery::update(
+                    &entry.id,
+                    DeliveryStatus::Dropped,
+                    Some("Expired in the outbox".to_string()),
+                );
+                continue;
+            }
+            send_msg.expiry_interval = Some(expiry_interval - waited);
+        }
+
+        let msg = match send_msg.to_mqtt() {
+            Ok(msg) => msg,
+            Err(err) => {
+                OutboxMessage::set_status(&entry.id, OutboxStatus::Failed).await?;
+                delivery::update(&entry.id, DeliveryStatus::Failed, Some(err.to_string()));
+                continue;
+            }
+        };
+
+        let token = clint.publish(msg);
+        in_flight.insert(entry.id.clone());
+        tokio::spawn(outbox_acked(
+            token,
+            clint.clone(),
+            name.to_string(),
+            entry,
+            acked_tx.clone(),
+        ));
     }
+    Ok(false)
+}
 
+/// Record the outcome of a publish from the outbox
+async fn outbox_acked(
+    token: mqtt::DeliveryToken,
+    clint: AsyncClient,
+    name: String,
+    entry: OutboxMessage,
+    acked_tx: UnboundedSender<Acked>,
+) {
+    let result = match acknowledged(token, &name, &entry.topic).await {
+        Ok(()) => OutboxMessage::set_status(&entry.id, OutboxStatus::Sent)
+            .await
+            .map(|_| {
+                delivery::update(&entry.id, DeliveryStatus::Published, None);
+                true
+            }),
+        // refusals only count while connected, the message waits for the next connection
+        Err(_) if !clint.is_connected() => Ok(false),
+        Err(err) => outbox_refused(&entry, err).await.map(|_| false),
+    };
+
+    let success = result.unwrap_or_else(|err| {
+        error!("Error updating the outbox: {}", err);
+        false
+    });
+    let _ = acked_tx.send(Acked {
+        connection: name,
+        publish: Publish::Outbox(entry.id),
+        success,
+    });
+}
+
+async fn outbox_refused(entry: &OutboxMessage, err: anyhow::Error) -> anyhow::Result<()> {
+    let attempts = entry.record_attempt(&err.to_string()).await?;
+    if attempts >= OUTBOX_MAX_ATTEMPTS {
+        error!(
+            "Message to {} refused {} times, given up: {}",
+            entry.topic, attempts, err
+        );
+        OutboxMessage::set_status(&entry.id, OutboxStatus::Failed).await?;
+        delivery::update(&entry.id, DeliveryStatus::Failed, Some(err.to_string()));
+    }
     Ok(())
 }
 
-fn get_mqtt_clint(config: &TymeConfig) -> anyhow::Result<AsyncClient> {
-    let host = if config.mqtt_config.ssl.enable {
-        format!(
-            "mqtts://{}:{}",
-            config.mqtt_config.broker, config.mqtt_config.port
-        )
+/// Remove the finished messages older than the retention of their connection
+async fn purge_outbox(config: &TymeConfig) {
+    for broker in config.connections() {
+        let retention = chrono::Duration::hours(broker.mqtt_config.outbox.retention_hours as i64);
+        match OutboxMessage::purge_finished(&broker.name, Local::now() - retention).await {
+            Ok(0) => {}
+            Ok(count) => info!(
+                "Purged {} finished message(s) from the outbox of {}",
+                count, broker.name
+            ),
+            Err(err) => error!("Error purging the outbox of {}: {}", broker.name, err),
+        }
+    }
+}
+
+/// Connect to one broker and start receiving its messages
+async fn start_connection(
+    broker: &BrokerConfig,
+    rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
+) -> anyhow::Result<AsyncClient> {
+    let mut clint = get_mqtt_clint(broker)?;
+    let headers = Header::get_connection_headers(&broker.name).await?;
+    HEADERS.lock().insert(broker.name.clone(), headers);
+
+    let sub_clint = clint.clone();
+    let strm = clint.get_stream(None);
+    tokio::spawn(subscribe(strm, sub_clint, rec_msg_tx, broker.clone()));
+
+    let conn_opts = get_conn_option(&broker.mqtt_config)?;
+    if let Err(err) = connect(&clint, &broker.name, conn_opts.clone()).await {
+        // the other brokers start anyway, messages for this one wait in the outbox
+        error!("Error connecting to {}: {}", broker.name, err);
+        status_error(&broker.name, &err);
+        tokio::spawn(connect_later(clint.clone(), broker.clone(), conn_opts));
+    }
+
+    Ok(clint)
+}
+
+/// Retry a broker that was unreachable at startup, waiting longer after every failed attempt
+async fn connect_later(clint: AsyncClient, broker: BrokerConfig, conn_opts: mqtt::ConnectOptions) {
+    let mut attempt = 0;
+    update_status(&broker.name, |status| {
+        status.state = ConnectionState::Reconnecting
+    });
+    loop {
+        tokio::time::sleep(backoff(&broker.mqtt_config.reconnect, attempt)).await;
+        match connect(&clint, &broker.name, conn_opts.clone()).await {
+            Ok(()) => {
+                info!("Connected to {}.", broker.name);
+                return;
+            }
+            Err(err) => {
+                error!(
+                    "Error connecting to {} (attempt {}): {}",
+                    broker.name,
+                    attempt + 1,
+                    err
+                );
+                attempt += 1;
+                status_error(&broker.name, &err);
+                update_status(&broker.name, |status| status.reconnect_attempts = attempt);
+            }
+        }
+    }
+}
+
+fn get_mqtt_clint(broker: &BrokerConfig) -> anyhow::Result<AsyncClient> {
+    let config = &broker.mqtt_config;
+    let host = if config.ssl.enable {
+        format!("mqtts://{}:{}", config.broker, config.port)
     } else {
-        format!(
-            "mqtt://{}:{}",
-            config.mqtt_config.broker, config.mqtt_config.port
-        )
+        format!("mqtt://{}:{}", config.broker, config.port)
     };
 
-    log::info!("Connecting to the MQTT server at '{}'...", host);
+    log::info!(
+        "Connecting to the MQTT server {} at '{}'...",
+        broker.name,
+        host
+    );
+    update_status(&broker.name, |status| {
+        status.state = ConnectionState::Connecting;
+        status.broker = host.clone();
+    });
 
     let create_opts = mqtt::CreateOptionsBuilder::new()
         .server_uri(host)
@@ -69,27 +638,25 @@ fn get_mqtt_clint(config: &TymeConfig) -> anyhow::Result<AsyncClient> {
     Ok(clint)
 }
 
-fn get_conn_option(config: &TymeConfig) -> anyhow::Result<mqtt::ConnectOptions> {
+fn get_conn_option(config: &MQTTConfig) -> anyhow::Result<mqtt::ConnectOptions> {
     let mut conn_opts = mqtt::ConnectOptionsBuilder::new();
 
     conn_opts.keep_alive_interval(Duration::from_secs(
-        config.mqtt_config.keep_alive_interval.unwrap_or(60),
+        config.keep_alive_interval.unwrap_or(60),
     ));
 
-    if config.mqtt_config.ssl.enable {
+    if config.ssl.enable {
         let ssl_opts = get_ssl_options(config)?;
         conn_opts.ssl_options(ssl_opts);
     }
 
-    if config.mqtt_config.auth.enable {
+    if config.auth.enable {
         let username = config
-            .mqtt_config
             .auth
             .username
             .clone()
             .context("The username config is none")?;
         let password = config
-            .mqtt_config
             .auth
             .password
             .clone()
@@ -106,6 +673,11 @@ fn get_conn_option(config: &TymeConfig) -> anyhow::Result<mqtt::ConnectOptions>
         ephemeral: true,
         message_type: String::from("text/markdown; charset=UTF-8"),
         raw: String::new(),
+        encoding: PayloadEncoding::Utf8,
+        connection: None,
+        id: None,
+        expiry_interval: None,
+        ttl: None,
     };
 
     conn_opts.will_message(lwt_msg.to_mqtt()?);
@@ -114,48 +686,82 @@ fn get_conn_option(config: &TymeConfig) -> anyhow::Result<mqtt::ConnectOptions>
         .clean_start(true)
         .properties(mqtt::properties![mqtt::PropertyCode::SessionExpiryInterval => 3600]);
 
-    conn_opts.ssl_options(get_ssl_options(config)?);
-
     Ok(conn_opts.finalize())
 }
 
-fn get_ssl_options(config: &TymeConfig) -> anyhow::Result<mqtt::SslOptions> {
+fn get_ssl_options(config: &MQTTConfig) -> anyhow::Result<mqtt::SslOptions> {
+    let ssl = &config.ssl;
     let mut ssl_opts = mqtt::SslOptionsBuilder::new();
 
-    let trust_store = config
-        .mqtt_config
-        .ssl
-        .trust_store
-        .clone()
-        .context("The trust store config is none")?;
-
-    let trust_store = crate::start_param
-        .word_dir
-        .clone()
-        .join("ssl")
-        .join(trust_store);
-
-    if !trust_store.exists() {
-        return Err(anyhow::anyhow!(
-            "The trust store file does not exist: {:?}",
-            trust_store
-        ));
-    };
+    if let Some(trust_store) = &ssl.trust_store {
+        ssl_opts.trust_store(ssl_file(trust_store)?)?;
+    }
 
-    ssl_opts.trust_store(trust_store)?.finalize();
+    if let Some(ca_path) = &ssl.ca_path {
+        ssl_opts.ca_path(ssl_file(ca_path)?)?;
+    }
+
+    if let Some(key_store) = &ssl.key_store {
+        ssl_opts.key_store(ssl_file(key_store)?)?;
+    }
+
+    if let Some(private_key) = &ssl.private_key {
+        ssl_opts.private_key(ssl_file(private_key)?)?;
+    }
+
+    if let Some(password) = &ssl.private_key_password {
+        ssl_opts.private_key_password(password.clone());
+    }
+
+    if let Some(protos) = &ssl.protos {
+        let protos = protos.iter().map(String::as_str).collect::<Vec<_>>();
+        ssl_opts.alpn_protos(&protos);
+    }
+
+    ssl_opts
+        .enable_server_cert_auth(ssl.verify_server_cert.unwrap_or(true))
+        .verify(ssl.verify_hostname.unwrap_or(true));
 
     Ok(ssl_opts.finalize())
 }
 
+/// A file of the TLS config, relative paths are under `workdir/ssl`
+fn ssl_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<std::path::PathBuf> {
+    let path = crate::start_param.word_dir.join("ssl").join(path);
+
+    if !path.exists() {
+        return Err(anyhow::anyhow!("The ssl file does not exist: {:?}", path));
+    };
+    Ok(path)
+}
+
 async fn subscribe_topic(
-    clint: AsyncClient,
-    mut sub_header_tx: UnboundedReceiver<Header>,
+    clints: HashMap<String, AsyncClient>,
+    mut sub_header_rx: UnboundedReceiver<HeaderCommand>,
 ) -> anyhow::Result<()> {
-    while let Some(header) = sub_header_tx.recv().await {
-        let topic = header.topic.clone();
-        let qos = header.qos;
-        if let Err(err) = clint.subscribe(topic, qos).await {
-            error!("Error subscribing to topic: {}", err);
+    while let Some(command) = sub_header_rx.recv().await {
+        let (HeaderCommand::Subscribe(header) | HeaderCommand::Unsubscribe(header)) = &command;
+        let Some(clint) = clints.get(&header.connection) else {
+            error!("Unknown broker connection {}", header.connection);
+            continue;
+        };
+
+        update_headers(&command);
+        match command {
+            HeaderCommand::Subscribe(header) => {
+                let sub_opts = mqtt::SubscribeOptions::with_retain_as_published();
+                if let Err(err) = clint
+                    .subscribe_with_options(header.topic.clone(), header.qos, sub_opts, None)
+                    .await
+                {
+                    error!("Error subscribing to topic {}: {}", header.topic, err);
+                }
+            }
+            HeaderCommand::Unsubscribe(header) => {
+                if let Err(err) = clint.unsubscribe(header.topic.clone()).await {
+                    error!("Error unsubscribing from topic {}: {}", header.topic, err);
+                }
+            }
         }
     }
     Ok(())
@@ -165,15 +771,16 @@ async fn subscribe(
     mut strm: AsyncReceiver<Option<mqtt::Message>>,
     clint: AsyncClient,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
+    broker: BrokerConfig,
 ) {
     while let Some(msg_opt) = strm.next().await {
         if let Some(msg) = msg_opt {
             info!(
-                "{} <<< [{:02}] ({}) {:?} : {:?}",
+                "{} <<< [{:02}] ({}) {} bytes : {:?}",
                 if msg.retained() { "(R)" } else { "" },
                 msg.qos(),
                 msg.topic(),
-                msg.payload_str(),
+                msg.payload().len(),
                 msg.properties()
             );
 
@@ -183,56 +790,128 @@ async fn subscribe(
             let ephemeral =
                 msg.properties().find_user_property("ephemeral") == Some("true".to_string());
 
-            match RecMessage::try_from(&msg) {
-                Ok(mut rec_msg) => {
+            // the header is looked up first, it gives the content type of external messages
+            match Header::find(&broker.name, msg.topic()).await {
+                Ok(Some(header)) => {
+                    let mut rec_msg = RecMessage::from_mqtt(&msg, header.default_type.as_deref());
                     if let Err(err) = rec_msg.to_html() {
                         error!("Error converting message to html: {}", err);
                     } else {
-                        match rec_msg.get_header().await {
-                            Ok(Some(header)) => {
-                                if rec_msg_tx.receiver_count() > 0 {
-                                    if let Err(err) =
-                                        rec_msg_tx.send((header.clone(), rec_msg.clone()))
-                                    {
-                                        error!("Error sending message: {}", err);
-                                    };
-                                }
-
-                                tokio::spawn(async move {
-                                    if !ephemeral {
-                                        if let Err(err) = rec_msg.insert(&header.id).await {
-                                            error!("Error inserting message: {}", err);
-                                        };
-                                    }
-                                });
-                            }
-                            Ok(None) => {
-                                error!("No header found for message: {:?}", rec_msg);
+                        crate::metrics::message_received(&broker.name, &header.topic);
+                        if rec_msg_tx.receiver_count() > 0 {
+                            if let Err(err) = rec_msg_tx.send((header.clone(), rec_msg.clone())) {
+                                error!("Error sending message: {}", err);
+                            };
+                        }
+
+                        tokio::spawn(async move {
+                            if !ephemeral {
+                                if let Err(err) = rec_msg.insert(&header.id).await {
+                                    error!("Error inserting message: {}", err);
+                                };
                             }
-                            Err(err) => {
-                                error!("Error getting header: {}", err);
-                            }
-                        };
+                        });
                     };
                 }
+                Ok(None) => {
+                    error!("No header found for message on topic: {}", msg.topic());
+                }
                 Err(err) => {
-                    error!("Error converting message: {}", err);
+                    error!("Error getting header: {}", err);
                 }
             }
         } else {
-            info!("Lost connection. Attempting reconnect.");
-            while let Err(err) = clint.reconnect().await {
-                error!("Error reconnecting: {}", err);
-                tokio::time::sleep(Duration::from_millis(1000)).await;
-            }
+            info!("Lost connection to {}. Attempting reconnect.", broker.name);
+            update_status(&broker.name, |status| {
+                status.state = ConnectionState::Reconnecting;
+                status.connected_since = None;
+            });
+            reconnect(&clint, &broker.name, &broker.mqtt_config.reconnect).await;
+            crate::metrics::reconnected(&broker.name);
             info!("Reconnected.");
         }
     }
 }
 
-async fn connect(clint: &AsyncClient, conn_opts: mqtt::ConnectOptions) -> anyhow::Result<()> {
+/// Retry until the broker is back, waiting longer after every failed attempt
+async fn reconnect(clint: &AsyncClient, name: &str, config: &Reconnect) {
+    let mut attempt = 0;
+    let rsp = loop {
+        tokio::time::sleep(backoff(config, attempt)).await;
+        match clint.reconnect().await {
+            Ok(rsp) => break rsp,
+            Err(err) => {
+                error!("Error reconnecting (attempt {}): {}", attempt + 1, err);
+                attempt += 1;
+                status_error(name, &err);
+                update_status(name, |status| status.reconnect_attempts = attempt);
+            }
+        }
+    };
+    status_connected(name, &rsp);
+
+    let session_present = rsp
+        .connect_response()
+        .is_some_and(|conn_rsp| conn_rsp.session_present);
+    if session_present {
+        info!("Client session resumed by the broker.");
+    } else if config.resubscribe {
+        // headers added since the first connect are only known to the database
+        if let Err(err) = subscribe_all(clint, name).await {
+            error!("Error subscribing to topics: {}", err);
+        }
+    }
+}
+
+/// Delay before an attempt after `attempt` failed ones
+fn backoff(config: &Reconnect, attempt: u32) -> Duration {
+    let delay =
+        config.initial_delay as f64 * config.multiplier.max(1.0).powi(attempt.min(64) as i32);
+    let delay = delay.min(config.max_delay as f64);
+    let jitter = config.jitter.clamp(0.0, 1.0) * rand::thread_rng().gen_range(0.0..=1.0);
+    Duration::from_millis((delay * (1.0 - jitter)) as u64)
+}
+
+/// Keep the headers known for labels in step with the subscriptions
+fn update_headers(command: &HeaderCommand) {
+    let mut headers = HEADERS.lock();
+    match command {
+        HeaderCommand::Subscribe(header) => {
+            let headers = headers.entry(header.connection.clone()).or_default();
+            match headers.iter_mut().find(|known| known.id == header.id) {
+                Some(known) => *known = header.clone(),
+                None => headers.insert(headers.len().saturating_sub(1), header.clone()),
+            }
+        }
+        HeaderCommand::Unsubscribe(header) => {
+            if let Some(headers) = headers.get_mut(&header.connection) {
+                headers.retain(|known| known.id != header.id);
+            }
+        }
+    }
+}
+
+/// Topic filter of the first header of the connection matching `topic`, used to label metrics
+fn header_label(connection: &str, topic: &str) -> String {
+    HEADERS
+        .lock()
+        .get(connection)
+        .and_then(|headers| {
+            headers
+                .iter()
+                .find(|header| header.mqtt_topic_matches(topic))
+                .map(|header| header.topic.clone())
+        })
+        .unwrap_or_default()
+}
+
+async fn connect(
+    clint: &AsyncClient,
+    name: &str,
+    conn_opts: mqtt::ConnectOptions,
+) -> anyhow::Result<()> {
     let rsp = clint.connect(conn_opts).await?;
-    let headers = Header::get_all_header().await?.into_iter();
+    status_connected(name, &rsp);
 
     if let Some(conn_rsp) = rsp.connect_response() {
         info!(
@@ -243,25 +922,81 @@ async fn connect(clint: &AsyncClient, conn_opts: mqtt::ConnectOptions) -> anyhow
         if conn_rsp.session_present {
             info!("Client session already present on broker.");
         } else {
-            info!(
-                r#"Subscribing to topics [{}]..."#,
-                headers
-                    .clone()
-                    .map(|x| format!("{{topic:{:?},qos:{}}}", x.topic, x.qos))
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            );
-            let sub_opts = vec![mqtt::SubscribeOptions::with_retain_as_published(); headers.len()];
+            subscribe_all(clint, name).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Subscribe to the topics of every header of the connection
+async fn subscribe_all(clint: &AsyncClient, name: &str) -> anyhow::Result<()> {
+    let headers = Header::get_connection_headers(name).await?.into_iter();
+
+    info!(
+        r#"Subscribing to topics [{}]..."#,
+        headers
+            .clone()
+            .map(|x| format!("{{topic:{:?},qos:{}}}", x.topic, x.qos))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    let sub_opts = vec![mqtt::SubscribeOptions::with_retain_as_published(); headers.len()];
 
-            let qos = headers.clone().map(|x| x.qos).collect::<Vec<i32>>();
+    let qos = headers.clone().map(|x| x.qos).collect::<Vec<i32>>();
 
-            let topics = headers.map(|x| x.topic).collect::<Vec<String>>();
+    let topics = headers.map(|x| x.topic).collect::<Vec<String>>();
 
-            clint
-                .subscribe_many_with_options(&topics, &qos, &sub_opts, None)
-                .await?;
+    clint
+        .subscribe_many_with_options(&topics, &qos, &sub_opts, None)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reconnect(multiplier: f64, jitter: f64) -> Reconnect {
+        Reconnect {
+            initial_delay: 1000,
+            max_delay: 30_000,
+            multiplier,
+            jitter,
+            resubscribe: true,
         }
     }
 
-    Ok(())
+    #[test]
+    fn backoff_grows_up_to_the_max_delay() {
+        let config = reconnect(2.0, 0.0);
+        let delays = (0..7)
+            .map(|attempt| backoff(&config, attempt).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1000, 2000, 4000, 8000, 16_000, 30_000, 30_000]);
+        assert_eq!(backoff(&config, u32::MAX), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn backoff_never_shrinks() {
+        let config = reconnect(0.5, 0.0);
+        assert_eq!(backoff(&config, 0), Duration::from_millis(1000));
+        assert_eq!(backoff(&config, 5), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn backoff_jitter_only_shortens_the_delay() {
+        let config = reconnect(2.0, 0.5);
+        for _ in 0..100 {
+            let delay = backoff(&config, 3);
+            assert!(delay >= Duration::from_millis(4000) && delay <= Duration::from_millis(8000));
+        }
+
+        // a jitter above 1 is taken as 1
+        let config = reconnect(2.0, 3.0);
+        for _ in 0..100 {
+            assert!(backoff(&config, 3) <= Duration::from_millis(8000));
+        }
+    }
 }
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{self, OverflowPolicy},
+    message::SendMessage,
+};
+
+/// A publish kept in the database until the broker takes it
+#[derive(Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct OutboxMessage {
+    pub id: String,
+    pub connection: String,
+    pub topic: String,
+    pub message: sqlx::types::Json<SendMessage>,
+    #[sqlx(try_from = "String")]
+    pub status: OutboxStatus,
+    /// publishes the broker refused while connected
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// waiting for the broker
+    #[default]
+    Pending,
+    Sent,
+    /// given way to other messages when the queue was full, or discarded by hand
+    Dropped,
+    /// refused by the broker too many times
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Sent => "sent",
+            OutboxStatus::Dropped => "dropped",
+            OutboxStatus::Failed => "failed",
+        }
+    }
+}
+
+impl TryFrom<String> for OutboxStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "pending" => Ok(OutboxStatus::Pending),
+            "sent" => Ok(OutboxStatus::Sent),
+            "dropped" => Ok(OutboxStatus::Dropped),
+            "failed" => Ok(OutboxStatus::Failed),
+            _ => Err(anyhow::anyhow!("Unknown outbox status: {}", value)),
+        }
+    }
+}
+
+/// What makes room for a new message
+#[derive(Debug, PartialEq, Eq)]
+enum Overflow {
+    /// the queue has room left
+    None,
+    /// this many of the oldest pending messages are dropped
+    DropOldest(i64),
+    /// the new message is dropped
+    DropNewest,
+}
+
+/// `pending` messages already wait in the queue
+fn overflow(pending: i64, config: &config::Outbox) -> Overflow {
+    let capacity = config.capacity as i64;
+    if pending < capacity {
+        return Overflow::None;
+    }
+    match config.overflow {
+        OverflowPolicy::DropOldest => Overflow::DropOldest(pending - capacity + 1),
+        OverflowPolicy::DropNewest => Overflow::DropNewest,
+    }
+}
+
+/// Queue the message for the connection, applying the overflow policy when the queue is full
+pub async fn enqueue(
+    connection: &str,
+    message: SendMessage,
+    config: &config::Outbox,
+) -> anyhow::Result<OutboxStatus> {
+    let pending = OutboxMessage::count_pending(connection).await?;
+    let mut status = OutboxStatus::Pending;
+
+    match overflow(pending, config) {
+        Overflow::None => {}
+        Overflow::DropOldest(count) => {
+            let dropped = OutboxMessage::drop_oldest(connection, count).await?;
+            warn!(
+                "Outbox of {} is full, dropped {} oldest message(s)",
+                connection, dropped
+            );
+        }
+        Overflow::DropNewest => {
+            warn!(
+                "Outbox of {} is full, message to {} dropped",
+                connection, message.topic
+            );
+            status = OutboxStatus::Dropped;
+        }
+    }
+
+    OutboxMessage::insert(connection, message, status).await?;
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outbox(capacity: u32, overflow: OverflowPolicy) -> config::Outbox {
+        config::Outbox {
+            capacity,
+            overflow,
+            retention_hours: 24,
+        }
+    }
+
+    #[test]
+    fn room_left_drops_nothing() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            assert_eq!(overflow(0, &outbox(3, policy)), Overflow::None);
+            assert_eq!(overflow(2, &outbox(3, policy)), Overflow::None);
+        }
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let config = outbox(3, OverflowPolicy::DropOldest);
+        assert_eq!(overflow(3, &config), Overflow::DropOldest(1));
+        // the capacity was lowered since the messages were queued
+        assert_eq!(overflow(5, &config), Overflow::DropOldest(3));
+    }
+
+    #[test]
+    fn full_queue_drops_the_newest() {
+        let config = outbox(3, OverflowPolicy::DropNewest);
+        assert_eq!(overflow(3, &config), Overflow::DropNewest);
+        assert_eq!(overflow(5, &config), Overflow::DropNewest);
+    }
+
+    #[test]
+    fn zero_capacity_is_always_full() {
+        assert_eq!(
+            overflow(0, &outbox(0, OverflowPolicy::DropOldest)),
+            Overflow::DropOldest(1)
+        );
+        assert_eq!(
+            overflow(0, &outbox(0, OverflowPolicy::DropNewest)),
+            Overflow::DropNewest
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use mlua::{Lua, LuaSerdeExt, RegistryKey};
+
+use crate::{config::TymeConfig, header::Header, message::RecMessage, task::Task};
+
+use super::{ScriptEngine, ScriptHost};
+
+const LIFECYCLE_CALLBACKS: [&str; 4] = ["on_start", "on_tick", "on_message", "on_stop"];
+
+/// First line of a script declaring itself a module
+const MODULE_MARKER: &str = "-- tyme:module";
+
+/// A lua task script and the lua state it keeps between ticks.
+///
+/// A plain script is executed again on every tick. A script whose first line is
+/// `-- tyme:module` is a module, its chunk returns a table with any of `on_start(ctx)`,
+/// `on_tick(ctx)`, `on_message(ctx, msg)` or `on_stop(ctx)`. The chunk of a module only runs
+/// once, when the task starts, followed by `on_start`, and every tick calls `on_tick`.
+/// `on_message` receives the messages matching the optional `topics` list of the module, or every
+/// message when it is absent. `ctx` is the same table for every call.
+/// The params of a `task_run_now` call are in `ctx.params`, or the global `params` of a plain
+/// script, and `nil` on scheduled runs. The value returned by the chunk of a plain script, or by
+/// `on_tick` of a module, is the output of the run.
+pub struct LuaEngine {
+    lua: Lua,
+    source: String,
+    ctx: RegistryKey,
+    /// declared by `MODULE_MARKER`
+    is_module: bool,
+    module: Option<RegistryKey>,
+    loaded: bool,
+}
+
+impl LuaEngine {
+    pub fn new(host: ScriptHost, task: &Task, source: String) -> anyhow::Result<Self> {
+        let lua = get_lua(host);
+
+        let ctx = lua.create_table()?;
+        ctx.set("id", task.id.clone())?;
+        ctx.set("name", task.name.clone())?;
+        ctx.set("tick", 0)?;
+        let ctx = lua.create_registry_value(ctx)?;
+        let is_module = source.lines().next().map(str::trim_end) == Some(MODULE_MARKER);
+
+        Ok(Self {
+            lua,
+            source,
+            ctx,
+            is_module,
+            module: None,
+            loaded: false,
+        })
+    }
+
+    async fn tick_lua(
+        &mut self,
+        params: Option<serde_json::Value>,
+    ) -> mlua::Result<Option<serde_json::Value>> {
+        {
+            let ctx: mlua::Table = self.lua.registry_value(&self.ctx)?;
+            ctx.set("tick", ctx.get::<_, i64>("tick")? + 1)?;
+
+            let params = match params {
+                Some(params) => self.lua.to_value(&params)?,
+                None => mlua::Value::Nil,
+            };
+            ctx.set("params", params.clone())?;
+            self.lua.globals().set("params", params)?;
+        }
+
+        let value = if self.is_module {
+            if !self.loaded {
+                self.load().await?;
+            }
+            self.call("on_tick", ()).await?
+        } else {
+            self.lua.load(&self.source).eval_async().await?
+        };
+        self.to_json(value)
+    }
+
+    /// Run the chunk of a module, keep the table it returns and call `on_start`
+    async fn load(&mut self) -> mlua::Result<()> {
+        self.loaded = true;
+        let value: mlua::Value = self.lua.load(&self.source).eval_async().await?;
+
+        let module = match value {
+            mlua::Value::Table(table)
+                if LIFECYCLE_CALLBACKS
+                    .iter()
+                    .any(|name| matches!(table.get(*name), Ok(mlua::Value::Function(_)))) =>
+            {
+                table
+            }
+            _ => {
+                return Err(mlua::Error::runtime(format!(
+                    "The script is marked with `{}` but returns none of {:?}",
+                    MODULE_MARKER, LIFECYCLE_CALLBACKS
+                )))
+            }
+        };
+        self.module = Some(self.lua.create_registry_value(module)?);
+        self.call("on_start", ()).await?;
+        Ok(())
+    }
+
+    /// Functions and userdata in the value are skipped
+    fn to_json(&self, value: mlua::Value) -> mlua::Result<Option<serde_json::Value>> {
+        match value {
+            mlua::Value::Nil => Ok(None),
+            value => self
+                .lua
+                .from_value_with(
+                    value,
+                    mlua::DeserializeOptions::new().deny_unsupported_types(false),
+                )
+                .map(Some),
+        }
+    }
+
+    fn module(&self) -> mlua::Result<Option<mlua::Table<'_>>> {
+        self.module
+            .as_ref()
+            .map(|module| self.lua.registry_value(module))
+            .transpose()
+    }
+
+    async fn on_message_lua(&self, msg: RecMessage) -> mlua::Result<()> {
+        let Some(module) = self.module()? else {
+            return Ok(());
+        };
+
+        if let Some(topics) = module.get::<_, Option<Vec<String>>>("topics")? {
+            let matched = topics.into_iter().any(|topic| {
+                Header {
+                    topic,
+                    ..Default::default()
+                }
+                .mqtt_topic_matches(&msg.topic)
+            });
+            if !matched {
+                return Ok(());
+            }
+        }
+
+        let msg = self.lua.to_value(&msg)?;
+        self.call("on_message", msg).await?;
+        Ok(())
+    }
+
+    /// Call a lifecycle callback with `ctx` as the first argument, missing ones are skipped
+    async fn call<'lua>(
+        &'lua self,
+        name: &str,
+        args: impl mlua::IntoLuaMulti<'lua>,
+    ) -> mlua::Result<mlua::Value<'lua>> {
+        let Some(module) = self.module()? else {
+            return Ok(mlua::Value::Nil);
+        };
+
+        match module.get::<_, mlua::Value>(name)? {
+            mlua::Value::Function(callback) => {
+                let mut args = args.into_lua_multi(&self.lua)?;
+                args.push_front(mlua::Value::Table(self.lua.registry_value(&self.ctx)?));
+                callback.call_async(args).await
+            }
+            _ => Ok(mlua::Value::Nil),
+        }
+    }
+}
+
+impl ScriptEngine for LuaEngine {
+    fn start(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            if self.is_module && !self.loaded {
+                self.load().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn tick(
+        &mut self,
+        params: Option<serde_json::Value>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Option<serde_json::Value>>> {
+        Box::pin(async move { Ok(self.tick_lua(params).await?) })
+    }
+
+    fn on_message(&mut self, msg: RecMessage) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move { Ok(self.on_message_lua(msg).await?) })
+    }
+
+    fn wants_messages(&self) -> bool {
+        self.module()
+            .ok()
+            .flatten()
+            .is_some_and(|module| matches!(module.get("on_message"), Ok(mlua::Value::Function(_))))
+    }
+
+    fn stop(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.call("on_stop", ()).await?;
+            Ok(())
+        })
+    }
+}
+
+struct TymeUserData {
+    host: ScriptHost,
+}
+
+impl mlua::UserData for TymeUserData {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("sys_config", get_sys_config);
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_async_method("send_json", lua_send_json);
+        methods.add_async_method("send_markdown", lua_send_markdown);
+        methods.add_async_method("sleep", lua_sleep);
+        methods.add_async_method("wait_for", lua_wait_for);
+        methods.add_async_method("secret", |_, tyme_user_data, name: String| async move {
+            tyme_user_data
+                .host
+                .secret(&name)
+                .await
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method("now", |_, tyme_user_data, ()| {
+            Ok(tyme_user_data.host.now().timestamp_millis())
+        });
+        methods.add_method("kv_get", lua_kv_get);
+        methods.add_method("kv_set", lua_kv_set);
+        methods.add_method("log", lua_log);
+        methods.add_method("task_list", lua_task_list);
+        methods.add_method("task_start", |_, tyme_user_data, id: String| {
+            tyme_user_data
+                .host
+                .task_start(&id)
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method("task_stop", |_, tyme_user_data, id: String| {
+            tyme_user_data
+                .host
+                .task_stop(&id)
+                .map_err(mlua::Error::external)
+        });
+        methods.add_method("task_run_now", lua_task_run_now);
+    }
+}
+
+fn get_sys_config(_: &mlua::Lua, tyme_user_data: &TymeUserData) -> mlua::Result<TymeConfig> {
+    Ok(tyme_user_data.host.config())
+}
+
+async fn lua_send_json(
+    _: &mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (topic, qos, ephemeral, json, connection): (String, i32, bool, mlua::Value<'_>, Option<String>),
+) -> mlua::Result<()> {
+    let json_string = serde_json::to_string(&json).map_err(mlua::Error::external)?;
+
+    tyme_user_data
+        .host
+        .publish(
+            topic,
+            qos,
+            ephemeral,
+            "application/json",
+            json_string,
+            connection,
+        )
+        .map_err(mlua::Error::external)
+}
+
+async fn lua_send_markdown(
+    _: &mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (topic, qos, ephemeral, markdown, connection): (
+        String,
+        i32,
+        bool,
+        mlua::Value<'_>,
+        Option<String>,
+    ),
+) -> mlua::Result<()> {
+    let markdown_string = markdown.to_string()?;
+
+    tyme_user_data
+        .host
+        .publish(
+            topic,
+            qos,
+            ephemeral,
+            "text/markdown",
+            markdown_string,
+            connection,
+        )
+        .map_err(mlua::Error::external)
+}
+
+async fn lua_sleep(_: &mlua::Lua, tyme_user_data: &TymeUserData, millis: u64) -> mlua::Result<()> {
+    tyme_user_data
+        .host
+        .sleep(Duration::from_millis(millis))
+        .await;
+    Ok(())
+}
+
+/// Wait for the next message matching `topic` (wildcards allowed), `nil` on timeout.
+/// Only topics covered by a subscribed header are received.
+async fn lua_wait_for<'lua>(
+    lua: &'lua mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (topic, timeout): (String, Option<u64>),
+) -> mlua::Result<mlua::Value<'lua>> {
+    let msg = tyme_user_data
+        .host
+        .wait_for(topic, timeout.map(Duration::from_millis))
+        .await;
+
+    match msg {
+        Some(msg) => lua.to_value(&msg),
+        None => Ok(mlua::Value::Nil),
+    }
+}
+
+fn lua_kv_get<'lua>(
+    lua: &'lua mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    key: String,
+) -> mlua::Result<mlua::Value<'lua>> {
+    match tyme_user_data.host.kv_get(&key) {
+        Some(value) => lua.to_value(&value),
+        None => Ok(mlua::Value::Nil),
+    }
+}
+
+fn lua_kv_set(
+    lua: &mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (key, value): (String, mlua::Value),
+) -> mlua::Result<()> {
+    let value = match value {
+        mlua::Value::Nil => None,
+        value => Some(lua.from_value(value)?),
+    };
+    tyme_user_data.host.kv_set(key, value);
+    Ok(())
+}
+
+fn lua_log(
+    _: &mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (level, msg): (String, String),
+) -> mlua::Result<()> {
+    tyme_user_data.host.log(&level, &msg);
+    Ok(())
+}
+
+fn lua_task_list<'lua>(
+    lua: &'lua mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    _: (),
+) -> mlua::Result<mlua::Value<'lua>> {
+    let tasks = tyme_user_data
+        .host
+        .task_list()
+        .map_err(mlua::Error::external)?;
+    lua.to_value(&tasks)
+}
+
+fn lua_task_run_now(
+    lua: &mlua::Lua,
+    tyme_user_data: &TymeUserData,
+    (id, params): (String, mlua::Value),
+) -> mlua::Result<()> {
+    let params = match params {
+        mlua::Value::Nil => None,
+        params => Some(lua.from_value(params)?),
+    };
+    tyme_user_data
+        .host
+        .task_run_now(&id, params)
+        .map_err(mlua::Error::external)
+}
+
+pub(super) fn get_lua(host: ScriptHost) -> mlua::Lua {
+    let lua = mlua::Lua::new();
+    let package_path = lua
+        .globals()
+        .get::<_, mlua::Table>("package")
+        .unwrap()
+        .get::<_, String>("path")
+        .unwrap();
+
+    let package_cpath = lua
+        .globals()
+        .get::<_, mlua::Table>("package")
+        .unwrap()
+        .get::<_, String>("cpath")
+        .unwrap();
+
+    let tyme_package_path = crate::start_param
+        .word_dir
+        .clone()
+        .join("script")
+        .join("?.lua");
+
+    let tyme_sys_package_path = std::env::current_dir().unwrap().join("?.lua");
+
+    #[cfg(target_os = "windows")]
+    let tyme_package_cpath = crate::start_param
+        .word_dir
+        .clone()
+        .join("script")
+        .join("?.dll");
+
+    #[cfg(not(target_os = "windows"))]
+    let tyme_package_cpath = crate::start_param
+        .word_dir
+        .clone()
+        .join("script")
+        .join("?.so");
+
+    let package_path = format!(
+        "{};{};{}",
+        package_path,
+        tyme_sys_package_path.display(),
+        tyme_package_path.display()
+    );
+    let package_cpath = format!("{};{}", package_cpath, tyme_package_cpath.display());
+
+    lua.globals()
+        .get::<_, mlua::Table>("package")
+        .unwrap()
+        .set("path", package_path)
+        .unwrap();
+
+    lua.globals()
+        .get::<_, mlua::Table>("package")
+        .unwrap()
+        .set("cpath", package_cpath)
+        .unwrap();
+
+    let tyme_user_data = TymeUserData { host };
+
+    lua.globals().set("tyme_sys", tyme_user_data).unwrap();
+
+    crate::sysinfo::register(&lua).unwrap();
+
+    lua
+}
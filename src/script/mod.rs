@@ -0,0 +1,375 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use futures::future::LocalBoxFuture;
+use parking_lot::Mutex;
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, mpsc::UnboundedSender},
+};
+
+use crate::{
+    config::TymeConfig,
+    header::Header,
+    message::{PayloadEncoding, RecMessage, SendMessage},
+    secret::Secret,
+    task::{Task, TaskManager},
+};
+
+mod lua_engine;
+mod rhai_engine;
+pub mod test_runner;
+
+pub use lua_engine::LuaEngine;
+pub use rhai_engine::RhaiEngine;
+
+/// How often `block_on` looks for a stop while waiting
+const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// File extensions of the scripts a task can run
+pub const SCRIPT_EXTENSIONS: [&str; 2] = ["lua", "rhai"];
+
+lazy_static! {
+    /// Values shared by every script through `kv_get` / `kv_set`
+    static ref KV: Mutex<HashMap<String, serde_json::Value>> = Mutex::new(HashMap::new());
+}
+
+/// A script loaded for a task.
+///
+/// Futures are not `Send`, engines live on the thread of the task that owns them.
+pub trait ScriptEngine {
+    /// Called once when the task starts, before the first tick: loads a module and calls
+    /// its `on_start`
+    fn start(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>>;
+
+    /// Run one execution, `params` are only set when the run was requested through `run_now`.
+    ///
+    /// Resolves to what the script returned, `None` for nil / unit.
+    fn tick(
+        &mut self,
+        params: Option<serde_json::Value>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Option<serde_json::Value>>>;
+
+    /// Deliver an incoming message, only called while `wants_messages` is true
+    fn on_message(&mut self, msg: RecMessage) -> LocalBoxFuture<'_, anyhow::Result<()>>;
+
+    fn wants_messages(&self) -> bool;
+
+    /// Called once when the task finishes or is stopped
+    fn stop(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Pick the engine by the extension of the task script
+pub fn load(
+    task: &Task,
+    host: ScriptHost,
+    source: String,
+) -> anyhow::Result<Box<dyn ScriptEngine>> {
+    let extension = Path::new(&task.script)
+        .extension()
+        .and_then(|ex| ex.to_str())
+        .unwrap_or_default();
+
+    match extension {
+        "lua" => Ok(Box::new(LuaEngine::new(host, task, source)?)),
+        "rhai" => Ok(Box::new(RhaiEngine::new(host, task, &source)?)),
+        _ => Err(anyhow::anyhow!(
+            "Unsupported script type: {}, expected one of {:?}",
+            task.script,
+            SCRIPT_EXTENSIONS
+        )),
+    }
+}
+
+/// The host api every engine exposes to scripts
+#[derive(Clone)]
+pub struct ScriptHost {
+    task_name: String,
+    send_msg_tx: UnboundedSender<SendMessage>,
+    rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
+    /// only set for tasks allowed to manage tasks
+    task_manager: Option<TaskManager>,
+    /// set by the test runner, replaces the broker, the kv store and the clock
+    mock: Option<Arc<Mutex<MockHost>>>,
+    /// runs the futures of `block_on`, `None` for a mocked host
+    runtime: Option<Handle>,
+    /// set when the task is stopped, engines that can check it abort the running script
+    interrupt: Arc<AtomicBool>,
+}
+
+/// What a `ScriptHost` talks to while running a script test
+pub struct MockHost {
+    /// only moved by sleeps, timeouts and the test itself
+    pub now: DateTime<Local>,
+    /// every message the script published
+    pub published: Vec<SendMessage>,
+    /// incoming messages handed to `wait_for`, oldest first
+    pub messages: VecDeque<RecMessage>,
+    pub kv: HashMap<String, serde_json::Value>,
+    pub secrets: HashMap<String, String>,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        Self {
+            now: Local::now(),
+            published: Vec::new(),
+            messages: VecDeque::new(),
+            kv: HashMap::new(),
+            secrets: HashMap::new(),
+        }
+    }
+}
+
+impl ScriptHost {
+    pub fn new(
+        task_name: String,
+        send_msg_tx: UnboundedSender<SendMessage>,
+        rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
+        task_manager: Option<TaskManager>,
+        runtime: Handle,
+        interrupt: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            task_name,
+            send_msg_tx,
+            rec_msg_tx,
+            task_manager,
+            mock: None,
+            runtime: Some(runtime),
+            interrupt,
+        }
+    }
+
+    /// A host that never leaves the process
+    pub fn mock(task_name: String, mock: Arc<Mutex<MockHost>>) -> Self {
+        let (send_msg_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let (rec_msg_tx, _) = broadcast::channel(1);
+
+        Self {
+            task_name,
+            send_msg_tx,
+            rec_msg_tx,
+            task_manager: None,
+            mock: Some(mock),
+            runtime: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn publish(
+        &self,
+        topic: String,
+        qos: i32,
+        ephemeral: bool,
+        message_type: &str,
+        raw: String,
+        connection: Option<String>,
+    ) -> anyhow::Result<()> {
+        let msg = SendMessage {
+            topic,
+            qos,
+            retain: None,
+            receiver: None,
+            ephemeral,
+            message_type: message_type.to_string(),
+            raw,
+            encoding: PayloadEncoding::Utf8,
+            connection,
+            id: None,
+            expiry_interval: None,
+            ttl: None,
+        };
+
+        match &self.mock {
+            Some(mock) => mock.lock().published.push(msg),
+            None => self.send_msg_tx.send(msg)?,
+        }
+        Ok(())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(Header, RecMessage)> {
+        self.rec_msg_tx.subscribe()
+    }
+
+    /// Wait for the next message matching `topic` (wildcards allowed), `None` on timeout
+    pub async fn wait_for(&self, topic: String, timeout: Option<Duration>) -> Option<RecMessage> {
+        let pattern = Header {
+            topic,
+            ..Default::default()
+        };
+
+        if let Some(mock) = &self.mock {
+            let mut mock = mock.lock();
+            let position = mock
+                .messages
+                .iter()
+                .position(|msg| pattern.mqtt_topic_matches(&msg.topic));
+            return match position {
+                Some(position) => mock.messages.remove(position),
+                None => {
+                    // nothing will ever arrive, let the timeout pass at once
+                    mock.now += timeout.unwrap_or_default();
+                    None
+                }
+            };
+        }
+
+        let mut rec_msg_rx = self.subscribe();
+        let wait = async {
+            loop {
+                match rec_msg_rx.recv().await {
+                    Ok((_, msg)) if pattern.mqtt_topic_matches(&msg.topic) => return Some(msg),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        };
+
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, wait).await.ok().flatten(),
+            None => wait.await,
+        }
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        match &self.mock {
+            Some(mock) => mock.lock().now += duration,
+            None => tokio::time::sleep(duration).await,
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Local> {
+        match &self.mock {
+            Some(mock) => mock.lock().now,
+            None => Local::now(),
+        }
+    }
+
+    pub fn kv_get(&self, key: &str) -> Option<serde_json::Value> {
+        match &self.mock {
+            Some(mock) => mock.lock().kv.get(key).cloned(),
+            None => KV.lock().get(key).cloned(),
+        }
+    }
+
+    /// `None` removes the key
+    pub fn kv_set(&self, key: String, value: Option<serde_json::Value>) {
+        let update = |kv: &mut HashMap<String, serde_json::Value>| match value {
+            Some(value) => kv.insert(key, value),
+            None => kv.remove(&key),
+        };
+
+        match &self.mock {
+            Some(mock) => update(&mut mock.lock().kv),
+            None => update(&mut KV.lock()),
+        };
+    }
+
+    pub fn log(&self, level: &str, msg: &str) {
+        let level = level.parse().unwrap_or(log::Level::Info);
+        log::log!(level, "[{}] {}", self.task_name, msg);
+    }
+
+    /// Wait for a host future from synchronous code, such as a rhai function.
+    ///
+    /// The future runs on the server runtime while the calling thread blocks, only call it from
+    /// the blocking pool. `None` when the task is stopped meanwhile
+    pub fn block_on<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Option<T> {
+        let Some(runtime) = &self.runtime else {
+            // mocked host calls never wait
+            return Some(futures::executor::block_on(future));
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let handle = runtime.spawn(async move {
+            let _ = tx.send(future.await);
+        });
+        loop {
+            match rx.recv_timeout(INTERRUPT_CHECK_INTERVAL) {
+                Ok(value) => return Some(value),
+                Err(RecvTimeoutError::Timeout) if self.interrupted() => {
+                    handle.abort();
+                    return None;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.load(Ordering::Relaxed)
+    }
+
+    /// Let the script run again, for callbacks still due after the interrupt
+    pub fn clear_interrupt(&self) {
+        self.interrupt.store(false, Ordering::Relaxed);
+    }
+
+    pub fn config(&self) -> TymeConfig {
+        match &self.mock {
+            Some(_) => TymeConfig::default(),
+            None => crate::tyme_config.lock().clone().redacted(),
+        }
+    }
+
+    /// Decrypted value of a secret, `None` when there is no such secret
+    pub async fn secret(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if let Some(mock) = &self.mock {
+            return Ok(mock.lock().secrets.get(name).cloned());
+        }
+        Secret::get_value(name).await
+    }
+
+    fn task_manager(&self) -> anyhow::Result<&TaskManager> {
+        self.task_manager
+            .as_ref()
+            .context("This task is not allowed to manage tasks")
+    }
+
+    /// The same entries as the task list of the web console
+    pub fn task_list(&self) -> anyhow::Result<Vec<serde_json::Value>> {
+        let tasks = self.task_manager()?.get_all_task()?;
+        Ok(tasks
+            .into_iter()
+            .map(|(running, task)| {
+                serde_json::json!({
+                    "remaining": task.remaining_executions(),
+                    "task": task,
+                    "running": running,
+                })
+            })
+            .collect())
+    }
+
+    pub fn task_start(&self, id: &String) -> anyhow::Result<()> {
+        self.task_manager()?.start_task(id)
+    }
+
+    pub fn task_stop(&self, id: &String) -> anyhow::Result<()> {
+        self.task_manager()?.stop_task(id)
+    }
+
+    pub fn task_run_now(
+        &self,
+        id: &String,
+        params: Option<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        self.task_manager()?.run_now(id, params)
+    }
+}
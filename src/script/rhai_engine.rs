@@ -0,0 +1,443 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::future::LocalBoxFuture;
+use parking_lot::Mutex;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+
+use crate::{header::Header, message::RecMessage, task::Task};
+
+use super::{ScriptEngine, ScriptHost};
+
+const LIFECYCLE_CALLBACKS: [&str; 4] = ["on_start", "on_tick", "on_message", "on_stop"];
+
+/// A rhai task script.
+///
+/// Rhai scripts cannot touch the file system or spawn processes, they only see the host
+/// functions registered in `get_engine`. A script defining any of `on_start()`, `on_tick()`,
+/// `on_message(msg)` or `on_stop()` follows the same lifecycle as a lua module, except that the
+/// context is bound to `this` and the optional `topics` array is a top level variable.
+/// The params of a `task_run_now` call are in `this.params`, or the `params` variable of a plain
+/// script, and `()` on scheduled runs. The value of the last statement of a plain script, or
+/// the value returned by `on_tick()`, is the output of the run.
+/// Rhai is synchronous, the script runs on the blocking pool so that `sleep`, `wait_for` and
+/// `secret` never hold up the other tasks.
+pub struct RhaiEngine {
+    host: ScriptHost,
+    script: Arc<Mutex<RhaiScript>>,
+    on_message: bool,
+    loaded: bool,
+}
+
+/// The state of a rhai script, only touched from the blocking pool
+struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    ctx: Dynamic,
+    module: bool,
+    loaded: bool,
+}
+
+impl RhaiEngine {
+    pub fn new(host: ScriptHost, task: &Task, source: &str) -> anyhow::Result<Self> {
+        let engine = get_engine(host.clone());
+        let ast = engine.compile(source)?;
+
+        let module = ast
+            .iter_functions()
+            .any(|f| LIFECYCLE_CALLBACKS.contains(&f.name));
+        let on_message = module && ast.iter_functions().any(|f| f.name == "on_message");
+
+        let mut ctx = Map::new();
+        ctx.insert("id".into(), task.id.clone().into());
+        ctx.insert("name".into(), task.name.clone().into());
+        ctx.insert("tick".into(), Dynamic::from_int(0));
+
+        Ok(Self {
+            host,
+            script: Arc::new(Mutex::new(RhaiScript {
+                engine,
+                ast,
+                scope: Scope::new(),
+                ctx: ctx.into(),
+                module,
+                loaded: false,
+            })),
+            on_message,
+            loaded: false,
+        })
+    }
+
+    /// Run `f` on the blocking pool, a stopped task aborts it through the interrupt of the host
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut RhaiScript) -> Result<T, Box<EvalAltResult>> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let script = self.script.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&mut script.lock())).await??)
+    }
+}
+
+impl RhaiScript {
+    fn tick(
+        &mut self,
+        params: Option<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, Box<EvalAltResult>> {
+        let params = match params {
+            Some(params) => rhai::serde::to_dynamic(params)?,
+            None => Dynamic::UNIT,
+        };
+
+        if let Some(mut ctx) = self.ctx.write_lock::<Map>() {
+            let tick = ctx.get("tick").and_then(|t| t.as_int().ok()).unwrap_or(0);
+            ctx.insert("tick".into(), Dynamic::from_int(tick + 1));
+            ctx.insert("params".into(), params.clone());
+        }
+
+        if !self.module {
+            let mut scope = Scope::new();
+            scope.push("params", params);
+            let value = self
+                .engine
+                .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)?;
+            return to_json(value);
+        }
+
+        self.start()?;
+        let value = self.call("on_tick", ())?;
+        to_json(value)
+    }
+
+    /// Run the top level statements of a module and call `on_start`, once
+    fn start(&mut self) -> Result<(), Box<EvalAltResult>> {
+        if self.module && !self.loaded {
+            self.loaded = true;
+            self.engine.run_ast_with_scope(&mut self.scope, &self.ast)?;
+            let _ = self.call("on_start", ())?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: RecMessage) -> Result<(), Box<EvalAltResult>> {
+        if let Some(topics) = self.scope.get_value::<rhai::Array>("topics") {
+            let matched = topics.into_iter().any(|topic| {
+                Header {
+                    topic: topic.to_string(),
+                    ..Default::default()
+                }
+                .mqtt_topic_matches(&msg.topic)
+            });
+            if !matched {
+                return Ok(());
+            }
+        }
+
+        let msg = rhai::serde::to_dynamic(&msg)?;
+        self.call("on_message", (msg,)).map(|_| ())
+    }
+
+    fn has_fn(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    /// Call a lifecycle callback with `this` bound to the context, missing ones are skipped
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> Result<Dynamic, Box<EvalAltResult>> {
+        if !self.module || !self.has_fn(name) {
+            return Ok(Dynamic::UNIT);
+        }
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .rewind_scope(true)
+            .bind_this_ptr(&mut self.ctx);
+
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args)
+    }
+}
+
+impl ScriptEngine for RhaiEngine {
+    fn start(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let result = self.run(|script| script.start()).await;
+            self.loaded = true;
+            result
+        })
+    }
+
+    fn tick(
+        &mut self,
+        params: Option<serde_json::Value>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Option<serde_json::Value>>> {
+        Box::pin(async move {
+            let result = self.run(move |script| script.tick(params)).await;
+            self.loaded = true;
+            result
+        })
+    }
+
+    fn on_message(&mut self, msg: RecMessage) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.run(move |script| script.on_message(msg)))
+    }
+
+    fn wants_messages(&self) -> bool {
+        self.loaded && self.on_message
+    }
+
+    fn stop(&mut self) -> LocalBoxFuture<'_, anyhow::Result<()>> {
+        let host = self.host.clone();
+        Box::pin(self.run(move |script| {
+            // an interrupted run released the script before this runs, the stop that set the
+            // interrupt is the one `on_stop` is called for
+            host.clear_interrupt();
+            script.call("on_stop", ()).map(|_| ())
+        }))
+    }
+}
+
+fn to_json(value: Dynamic) -> Result<Option<serde_json::Value>, Box<EvalAltResult>> {
+    if value.is_unit() {
+        Ok(None)
+    } else {
+        rhai::serde::from_dynamic(&value).map(Some)
+    }
+}
+
+/// Publish `json` on the named broker connection, the default one when `None`
+fn send_json(
+    host: &ScriptHost,
+    topic: &str,
+    qos: i64,
+    ephemeral: bool,
+    json: Dynamic,
+    connection: Option<&str>,
+) -> Result<(), Box<EvalAltResult>> {
+    let json_string = serde_json::to_string(&json).map_err(|e| e.to_string())?;
+    host.publish(
+        topic.to_string(),
+        qos as i32,
+        ephemeral,
+        "application/json",
+        json_string,
+        connection.map(str::to_string),
+    )
+    .map_err(|e| e.to_string().into())
+}
+
+fn send_markdown(
+    host: &ScriptHost,
+    topic: &str,
+    qos: i64,
+    ephemeral: bool,
+    markdown: &str,
+    connection: Option<&str>,
+) -> Result<(), Box<EvalAltResult>> {
+    host.publish(
+        topic.to_string(),
+        qos as i32,
+        ephemeral,
+        "text/markdown",
+        markdown.to_string(),
+        connection.map(str::to_string),
+    )
+    .map_err(|e| e.to_string().into())
+}
+
+/// The next message matching `topic` (wildcards allowed), `()` on timeout.
+/// Only topics covered by a subscribed header are received.
+fn wait_for(
+    host: &ScriptHost,
+    topic: &str,
+    timeout: Option<i64>,
+) -> Result<Dynamic, Box<EvalAltResult>> {
+    let wait_host = host.clone();
+    let topic = topic.to_string();
+    let timeout = timeout.map(|timeout| Duration::from_millis(timeout.max(0) as u64));
+    let msg = host
+        .block_on(async move { wait_host.wait_for(topic, timeout).await })
+        .ok_or("Task stopped")?;
+
+    match msg {
+        Some(msg) => rhai::serde::to_dynamic(msg),
+        None => Ok(Dynamic::UNIT),
+    }
+}
+
+fn get_engine(host: ScriptHost) -> Engine {
+    let mut engine = Engine::new();
+
+    // a script stuck in a loop neither returns nor sees the stop command
+    let progress_host = host.clone();
+    engine.on_progress(move |_| {
+        progress_host
+            .interrupted()
+            .then(|| Dynamic::from("Task stopped"))
+    });
+
+    let print_host = host.clone();
+    engine.on_print(move |text| print_host.log("info", text));
+
+    let debug_host = host.clone();
+    engine.on_debug(move |text, _, _| debug_host.log("debug", text));
+
+    let json_host = host.clone();
+    engine.register_fn(
+        "send_json",
+        move |topic: &str, qos: i64, ephemeral: bool, json: Dynamic| {
+            send_json(&json_host, topic, qos, ephemeral, json, None)
+        },
+    );
+
+    let json_host = host.clone();
+    engine.register_fn(
+        "send_json",
+        move |topic: &str, qos: i64, ephemeral: bool, json: Dynamic, connection: &str| {
+            send_json(&json_host, topic, qos, ephemeral, json, Some(connection))
+        },
+    );
+
+    let markdown_host = host.clone();
+    engine.register_fn(
+        "send_markdown",
+        move |topic: &str, qos: i64, ephemeral: bool, markdown: &str| {
+            send_markdown(&markdown_host, topic, qos, ephemeral, markdown, None)
+        },
+    );
+
+    let markdown_host = host.clone();
+    engine.register_fn(
+        "send_markdown",
+        move |topic: &str, qos: i64, ephemeral: bool, markdown: &str, connection: &str| {
+            send_markdown(
+                &markdown_host,
+                topic,
+                qos,
+                ephemeral,
+                markdown,
+                Some(connection),
+            )
+        },
+    );
+
+    let kv_host = host.clone();
+    engine.register_fn(
+        "kv_get",
+        move |key: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            match kv_host.kv_get(key) {
+                Some(value) => rhai::serde::to_dynamic(value),
+                None => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+
+    let kv_host = host.clone();
+    engine.register_fn(
+        "kv_set",
+        move |key: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let value = if value.is_unit() {
+                None
+            } else {
+                Some(rhai::serde::from_dynamic::<serde_json::Value>(&value)?)
+            };
+            kv_host.kv_set(key.to_string(), value);
+            Ok(())
+        },
+    );
+
+    let sleep_host = host.clone();
+    engine.register_fn(
+        "sleep",
+        move |millis: i64| -> Result<(), Box<EvalAltResult>> {
+            let host = sleep_host.clone();
+            sleep_host
+                .block_on(async move {
+                    host.sleep(Duration::from_millis(millis.max(0) as u64))
+                        .await
+                })
+                .ok_or_else(|| "Task stopped".into())
+        },
+    );
+
+    let wait_host = host.clone();
+    engine.register_fn("wait_for", move |topic: &str| {
+        wait_for(&wait_host, topic, None)
+    });
+
+    let wait_host = host.clone();
+    engine.register_fn("wait_for", move |topic: &str, timeout: i64| {
+        wait_for(&wait_host, topic, Some(timeout))
+    });
+
+    let secret_host = host.clone();
+    engine.register_fn(
+        "secret",
+        move |name: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let host = secret_host.clone();
+            let name = name.to_string();
+            let secret = secret_host
+                .block_on(async move { host.secret(&name).await })
+                .ok_or("Task stopped")?
+                .map_err(|e| e.to_string())?;
+            Ok(secret.map(Dynamic::from).unwrap_or(Dynamic::UNIT))
+        },
+    );
+
+    let now_host = host.clone();
+    engine.register_fn("now", move || now_host.now().timestamp_millis());
+
+    let log_host = host.clone();
+    engine.register_fn("log", move |level: &str, msg: &str| {
+        log_host.log(level, msg)
+    });
+
+    let task_host = host.clone();
+    engine.register_fn(
+        "task_list",
+        move || -> Result<Dynamic, Box<EvalAltResult>> {
+            let tasks = task_host.task_list().map_err(|e| e.to_string())?;
+            rhai::serde::to_dynamic(tasks)
+        },
+    );
+
+    let task_host = host.clone();
+    engine.register_fn(
+        "task_start",
+        move |id: &str| -> Result<(), Box<EvalAltResult>> {
+            task_host
+                .task_start(&id.to_string())
+                .map_err(|e| e.to_string().into())
+        },
+    );
+
+    let task_host = host.clone();
+    engine.register_fn(
+        "task_stop",
+        move |id: &str| -> Result<(), Box<EvalAltResult>> {
+            task_host
+                .task_stop(&id.to_string())
+                .map_err(|e| e.to_string().into())
+        },
+    );
+
+    let task_host = host.clone();
+    engine.register_fn(
+        "task_run_now",
+        move |id: &str, params: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let params = if params.is_unit() {
+                None
+            } else {
+                Some(rhai::serde::from_dynamic::<serde_json::Value>(&params)?)
+            };
+            task_host
+                .task_run_now(&id.to_string(), params)
+                .map_err(|e| e.to_string().into())
+        },
+    );
+
+    engine.register_fn(
+        "sys_config",
+        move || -> Result<Dynamic, Box<EvalAltResult>> { rhai::serde::to_dynamic(host.config()) },
+    );
+
+    engine
+}
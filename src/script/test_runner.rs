@@ -0,0 +1,251 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use mlua::{Lua, LuaSerdeExt};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::message::{MessageContent, PayloadEncoding, RecMessage};
+
+use super::{lua_engine::get_lua, MockHost, ScriptHost};
+
+/// Suffix of the files run by the test runner
+pub const TEST_SUFFIX: &str = "_test.lua";
+
+#[derive(Serialize)]
+pub struct TestReport {
+    pub file: String,
+    /// the file could not be loaded, no test ran
+    pub error: Option<String>,
+    pub tests: Vec<TestResult>,
+}
+
+#[derive(Serialize)]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.tests.iter().all(|test| test.passed)
+    }
+}
+
+/// Run the `*_test.lua` files under `workdir/script` whose name contains `filter`.
+///
+/// Every global function named `test_*` is a test. Each test gets a fresh lua state and a mocked
+/// `tyme_sys`: publishes are recorded, `kv_*` use an empty store, `sleep` and `wait_for` move a
+/// fake clock instead of waiting. A `test` table drives the mock:
+///
+/// - `test.published()` the messages published so far, `test.clear()` forgets them
+/// - `test.message(topic, raw, [type])` queues an incoming message for `wait_for`
+/// - `test.secret(name, value)` defines a secret for `secret`
+/// - `test.advance(millis)` moves the clock, `test.now()` reads it
+/// - `test.run(file, [params])` runs a script of the script dir and returns its value
+/// - `test.assert_eq(actual, expected, [message])` compares values deeply
+pub async fn run(filter: Option<String>) -> anyhow::Result<Vec<TestReport>> {
+    let script_dir = script_dir();
+
+    let mut files = Vec::new();
+    if script_dir.exists() {
+        for entry in script_dir.read_dir()? {
+            let file = entry?.file_name().to_string_lossy().to_string();
+            let matched = match &filter {
+                Some(filter) => file.contains(filter.as_str()),
+                None => true,
+            };
+            if file.ends_with(TEST_SUFFIX) && matched {
+                files.push(file);
+            }
+        }
+    }
+    files.sort();
+
+    let mut reports = Vec::new();
+    for file in files {
+        reports.push(run_file(file).await);
+    }
+    Ok(reports)
+}
+
+async fn run_file(file: String) -> TestReport {
+    let mut report = TestReport {
+        file: file.clone(),
+        error: None,
+        tests: Vec::new(),
+    };
+
+    let source = match tokio::fs::read_to_string(script_dir().join(&file)).await {
+        Ok(source) => source,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    let names = match load(&file, &source).await {
+        Ok(lua) => test_names(&lua),
+        Err(e) => Err(e),
+    };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    for name in names {
+        let result = async {
+            let lua = load(&file, &source).await?;
+            let test: mlua::Function = lua.globals().get(name.as_str())?;
+            test.call_async::<_, ()>(()).await
+        };
+
+        let error = result.await.err().map(|e| e.to_string());
+        report.tests.push(TestResult {
+            name,
+            passed: error.is_none(),
+            error,
+        });
+    }
+    report
+}
+
+fn script_dir() -> PathBuf {
+    crate::start_param.word_dir.clone().join("script")
+}
+
+/// Global functions named `test_*`, sorted
+fn test_names(lua: &Lua) -> mlua::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for pair in lua.globals().pairs::<mlua::Value, mlua::Value>() {
+        if let (mlua::Value::String(name), mlua::Value::Function(_)) = pair? {
+            let name = name.to_str()?;
+            if name.starts_with("test_") {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// A lua state with the mocked host, after running the chunk of the test file
+async fn load(file: &str, source: &str) -> mlua::Result<Lua> {
+    let mock = Arc::new(Mutex::new(MockHost::new()));
+    let lua = get_lua(ScriptHost::mock(file.to_string(), mock.clone()));
+
+    lua.globals().set("test", test_table(&lua, mock)?)?;
+    lua.load(source).set_name(file).exec_async().await?;
+    Ok(lua)
+}
+
+fn test_table(lua: &Lua, mock: Arc<Mutex<MockHost>>) -> mlua::Result<mlua::Table<'_>> {
+    let test = lua.create_table()?;
+
+    let published = mock.clone();
+    test.set(
+        "published",
+        lua.create_function(move |lua, ()| lua.to_value(&published.lock().published))?,
+    )?;
+
+    let clear = mock.clone();
+    test.set(
+        "clear",
+        lua.create_function(move |_, ()| {
+            clear.lock().published.clear();
+            Ok(())
+        })?,
+    )?;
+
+    let message = mock.clone();
+    test.set(
+        "message",
+        lua.create_function(
+            move |_, (topic, raw, message_type): (String, String, Option<String>)| {
+                let mut mock = message.lock();
+                let msg = RecMessage {
+                    id: nanoid::nanoid!(),
+                    topic,
+                    qos: 0,
+                    retain: false,
+                    mine: false,
+                    timestamp: mock.now,
+                    content: MessageContent {
+                        message_type: message_type.unwrap_or("text/plain".to_string()),
+                        raw,
+                        html: None,
+                        encoding: PayloadEncoding::Utf8,
+                    },
+                    sender: None,
+                    receiver: None,
+                    external: false,
+                    expires_at: None,
+                };
+                mock.messages.push_back(msg);
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let secret = mock.clone();
+    test.set(
+        "secret",
+        lua.create_function(move |_, (name, value): (String, String)| {
+            secret.lock().secrets.insert(name, value);
+            Ok(())
+        })?,
+    )?;
+
+    let advance = mock.clone();
+    test.set(
+        "advance",
+        lua.create_function(move |_, millis: u64| {
+            advance.lock().now += Duration::from_millis(millis);
+            Ok(())
+        })?,
+    )?;
+
+    test.set(
+        "now",
+        lua.create_function(move |_, ()| Ok(mock.lock().now.timestamp_millis()))?,
+    )?;
+
+    test.set(
+        "run",
+        lua.create_async_function(|lua, (file, params): (String, mlua::Value)| async move {
+            let source = tokio::fs::read_to_string(script_dir().join(&file))
+                .await
+                .map_err(mlua::Error::external)?;
+            lua.globals().set("params", params)?;
+            lua.load(source)
+                .set_name(file)
+                .eval_async::<mlua::Value>()
+                .await
+        })?,
+    )?;
+
+    test.set(
+        "assert_eq",
+        lua.create_function(
+            |lua, (actual, expected, message): (mlua::Value, mlua::Value, Option<String>)| {
+                let actual: serde_json::Value = lua.from_value(actual)?;
+                let expected: serde_json::Value = lua.from_value(expected)?;
+                if actual == expected {
+                    return Ok(());
+                }
+
+                Err(mlua::Error::runtime(format!(
+                    "{}expected {}, got {}",
+                    message.map(|m| format!("{}: ", m)).unwrap_or_default(),
+                    expected,
+                    actual
+                )))
+            },
+        )?,
+    )?;
+
+    Ok(test)
+}
@@ -0,0 +1,91 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Environment variable holding the passphrase the secrets are encrypted with
+pub const SECRET_KEY_ENV: &str = "TYME_SECRET_KEY";
+
+const NONCE_LEN: usize = 12;
+
+lazy_static! {
+    /// Taken out of the environment on first use, child processes and scripts never see it
+    static ref PASSPHRASE: Option<String> = {
+        let passphrase = std::env::var(SECRET_KEY_ENV)
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty());
+        std::env::remove_var(SECRET_KEY_ENV);
+        passphrase
+    };
+}
+
+/// A named credential for scripts, the value is never serialized
+#[derive(Deserialize, Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct Secret {
+    pub name: String,
+    #[serde(default, skip_serializing)]
+    #[sqlx(default)]
+    pub value: String,
+    pub remark: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Local>>,
+}
+
+impl Secret {
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("name is empty");
+        }
+        if self.value.is_empty() {
+            anyhow::bail!("value is empty");
+        }
+        Ok(())
+    }
+}
+
+/// Read the passphrase before any other thread or process could see the environment
+pub fn init() {
+    lazy_static::initialize(&PASSPHRASE);
+}
+
+fn cipher() -> anyhow::Result<Aes256Gcm> {
+    let passphrase = PASSPHRASE
+        .as_ref()
+        .with_context(|| format!("{} is not set, secrets are unavailable", SECRET_KEY_ENV))?;
+
+    let key = Sha256::digest(passphrase.as_bytes());
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+/// base64 of the nonce followed by the ciphertext
+pub fn encrypt(value: &str) -> anyhow::Result<String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher()?
+        .encrypt(&nonce, value.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Unable to encrypt secret"))?;
+
+    let mut stored = nonce.to_vec();
+    stored.extend(ciphertext);
+    Ok(STANDARD.encode(stored))
+}
+
+pub fn decrypt(stored: &str) -> anyhow::Result<String> {
+    let stored = STANDARD.decode(stored)?;
+    if stored.len() < NONCE_LEN {
+        anyhow::bail!("Malformed secret");
+    }
+
+    let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+    let value = cipher()?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            anyhow::anyhow!("Unable to decrypt secret, was {} changed?", SECRET_KEY_ENV)
+        })?;
+
+    Ok(String::from_utf8(value)?)
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use mlua::{Lua, LuaSerdeExt};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Name under which the module is reachable from scripts: `require("tyme.sys")`
+pub const MODULE_NAME: &str = "tyme.sys";
+
+#[derive(Serialize, Clone, Copy, Default)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+#[derive(Serialize)]
+pub struct CpuInfo {
+    /// busy percentage since the previous call, or since boot on the first call
+    pub load: f64,
+    pub cores: usize,
+}
+
+/// All values are in bytes
+#[derive(Serialize, Default)]
+pub struct MemoryInfo {
+    pub total: u64,
+    pub free: u64,
+    pub available: u64,
+    pub used: u64,
+    pub buffers: u64,
+    pub cached: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+}
+
+/// All values are in bytes
+#[derive(Serialize)]
+pub struct DiskInfo {
+    pub path: String,
+    pub total: u64,
+    pub free: u64,
+    pub available: u64,
+    pub used: u64,
+}
+
+#[derive(Serialize)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Serialize, Default)]
+pub struct NetworkCounters {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// Register `tyme.sys` into `package.loaded`, the cpu sample is kept per lua state
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let module = lua.create_table()?;
+    let prev_cpu = Arc::new(Mutex::new(None::<CpuTimes>));
+
+    let cpu_prev = prev_cpu.clone();
+    module.set(
+        "cpu",
+        lua.create_function(move |lua, ()| lua.to_value(&cpu(&cpu_prev)?))?,
+    )?;
+    module.set(
+        "memory",
+        lua.create_function(|lua, ()| lua.to_value(&memory()?))?,
+    )?;
+    module.set(
+        "disk",
+        lua.create_function(|lua, path: Option<String>| {
+            lua.to_value(&disk(path.as_deref().unwrap_or("/"))?)
+        })?,
+    )?;
+    module.set("uptime", lua.create_function(|_, ()| uptime())?)?;
+    module.set(
+        "load_average",
+        lua.create_function(|lua, ()| lua.to_value(&load_average()?))?,
+    )?;
+    module.set(
+        "network",
+        lua.create_function(|lua, ()| lua.to_value(&network()?))?,
+    )?;
+    module.set(
+        "info",
+        lua.create_function(move |lua, ()| {
+            let info = lua.create_table()?;
+            info.set("cpu", lua.to_value(&cpu(&prev_cpu)?)?)?;
+            info.set("memory", lua.to_value(&memory()?)?)?;
+            info.set("disk", lua.to_value(&disk("/")?)?)?;
+            info.set("uptime", uptime()?)?;
+            info.set("load_average", lua.to_value(&load_average()?)?)?;
+            info.set("network", lua.to_value(&network()?)?)?;
+            Ok(info)
+        })?,
+    )?;
+
+    lua.globals()
+        .get::<_, mlua::Table>("package")?
+        .get::<_, mlua::Table>("loaded")?
+        .set(MODULE_NAME, module)?;
+
+    Ok(())
+}
+
+fn read_proc(name: &str) -> mlua::Result<String> {
+    fs::read_to_string(format!("/proc/{}", name)).map_err(|err| {
+        mlua::Error::runtime(format!(
+            "{}: unable to read /proc/{}: {}",
+            MODULE_NAME, name, err
+        ))
+    })
+}
+
+fn parse_error(name: &str) -> mlua::Error {
+    mlua::Error::runtime(format!(
+        "{}: unexpected format of /proc/{}",
+        MODULE_NAME, name
+    ))
+}
+
+fn cpu(prev: &Mutex<Option<CpuTimes>>) -> mlua::Result<CpuInfo> {
+    let stat = read_proc("stat")?;
+    let fields = stat
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| parse_error("stat"))?
+        .split_whitespace()
+        .skip(1)
+        .map(|f| f.parse::<u64>().map_err(|_| parse_error("stat")))
+        .collect::<mlua::Result<Vec<u64>>>()?;
+
+    if fields.len() < 4 {
+        return Err(parse_error("stat"));
+    }
+
+    // user nice system idle iowait irq softirq steal ...
+    let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+    let total = fields.iter().take(8).sum::<u64>();
+    let current = CpuTimes {
+        busy: total - idle,
+        total,
+    };
+
+    let last = prev.lock().replace(current).unwrap_or_default();
+    let total_delta = current.total.saturating_sub(last.total);
+    let busy_delta = current.busy.saturating_sub(last.busy);
+
+    let load = if total_delta == 0 {
+        0.0
+    } else {
+        busy_delta as f64 * 100.0 / total_delta as f64
+    };
+
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    Ok(CpuInfo { load, cores })
+}
+
+fn memory() -> mlua::Result<MemoryInfo> {
+    let meminfo = read_proc("meminfo")?;
+    let values = meminfo
+        .lines()
+        .filter_map(|line| {
+            let (key, rest) = line.split_once(':')?;
+            let kb = rest.split_whitespace().next()?.parse::<u64>().ok()?;
+            Some((key, kb * 1024))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let get = |key: &str| values.get(key).copied().unwrap_or(0);
+
+    let total = get("MemTotal");
+    let free = get("MemFree");
+    let available = values.get("MemAvailable").copied().unwrap_or(free);
+
+    Ok(MemoryInfo {
+        total,
+        free,
+        available,
+        used: total.saturating_sub(available),
+        buffers: get("Buffers"),
+        cached: get("Cached"),
+        swap_total: get("SwapTotal"),
+        swap_free: get("SwapFree"),
+    })
+}
+
+#[cfg(unix)]
+fn disk(path: &str) -> mlua::Result<DiskInfo> {
+    use std::{ffi::CString, mem::MaybeUninit};
+
+    let c_path = CString::new(path).map_err(mlua::Error::external)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `c_path` is a valid nul terminated string and `stat` is only read on success
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(mlua::Error::runtime(format!(
+                "{}: unable to stat {}: {}",
+                MODULE_NAME,
+                path,
+                std::io::Error::last_os_error()
+            )));
+        }
+        stat.assume_init()
+    };
+
+    let block_size = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block_size;
+    let free = stat.f_bfree as u64 * block_size;
+    let available = stat.f_bavail as u64 * block_size;
+
+    Ok(DiskInfo {
+        path: path.to_string(),
+        total,
+        free,
+        available,
+        used: total.saturating_sub(free),
+    })
+}
+
+#[cfg(not(unix))]
+fn disk(_: &str) -> mlua::Result<DiskInfo> {
+    Err(mlua::Error::runtime(format!(
+        "{}: disk usage is not supported on this platform",
+        MODULE_NAME
+    )))
+}
+
+fn uptime() -> mlua::Result<f64> {
+    read_proc("uptime")?
+        .split_whitespace()
+        .next()
+        .and_then(|f| f.parse::<f64>().ok())
+        .ok_or_else(|| parse_error("uptime"))
+}
+
+fn load_average() -> mlua::Result<LoadAverage> {
+    let loadavg = read_proc("loadavg")?;
+    let mut fields = loadavg.split_whitespace().map(|f| f.parse::<f64>().ok());
+
+    let mut next = || {
+        fields
+            .next()
+            .flatten()
+            .ok_or_else(|| parse_error("loadavg"))
+    };
+
+    Ok(LoadAverage {
+        one: next()?,
+        five: next()?,
+        fifteen: next()?,
+    })
+}
+
+fn network() -> mlua::Result<HashMap<String, NetworkCounters>> {
+    let dev = read_proc("net/dev")?;
+    let mut interfaces = HashMap::new();
+
+    // the first two lines are headers
+    for line in dev.lines().skip(2) {
+        let (name, rest) = line.split_once(':').ok_or_else(|| parse_error("net/dev"))?;
+        let fields = rest
+            .split_whitespace()
+            .map(|f| f.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<u64>>();
+
+        if fields.len() < 12 {
+            return Err(parse_error("net/dev"));
+        }
+
+        interfaces.insert(
+            name.trim().to_string(),
+            NetworkCounters {
+                rx_bytes: fields[0],
+                rx_packets: fields[1],
+                rx_errors: fields[2],
+                rx_dropped: fields[3],
+                tx_bytes: fields[8],
+                tx_packets: fields[9],
+                tx_errors: fields[10],
+                tx_dropped: fields[11],
+            },
+        );
+    }
+
+    Ok(interfaces)
+}
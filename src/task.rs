@@ -1,79 +1,234 @@
 use anyhow::Context;
-use cron::Schedule;
 
+use chrono::{DateTime, Local, NaiveDate};
 use linked_hash_map::LinkedHashMap;
 use log::{error, info};
-use mlua::Lua;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::{ops::Deref, str::FromStr, sync::Arc};
-use tokio::sync::oneshot::Sender;
-
-use crate::config::TymeConfig;
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::runtime::Handle;
+use tokio::sync::{
+    broadcast,
+    mpsc::{UnboundedReceiver, UnboundedSender},
+};
+use tokio_util::task::LocalPoolHandle;
+
+use crate::{
+    calendar::Calendar,
+    command::{CommandOutput, CommandSpec},
+    header::Header,
+    message::RecMessage,
+    script::{self, ScriptEngine, ScriptHost, SCRIPT_EXTENSIONS},
+    trigger::Trigger,
+};
 
 #[derive(Clone)]
 pub struct TaskManager {
     send_msg_tx: tokio::sync::mpsc::UnboundedSender<crate::message::SendMessage>,
+    rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
+    /// the runtime of the server, synchronous script engines wait on it
+    runtime: Handle,
+    /// script futures are not `Send`, every task is pinned to one thread of this pool
+    pool: LocalPoolHandle,
     inner: Arc<Mutex<LinkedHashMap<String, TaskRunner>>>,
 }
 
 struct TaskRunner {
-    tx: Option<Sender<()>>,
-    task: Task,
+    tx: Option<UnboundedSender<TaskCommand>>,
+    /// shared with the running loop, which keeps the execution count up to date
+    task: Arc<Mutex<Task>>,
+    /// set on stop, interrupts a script stuck in a run
+    interrupt: Arc<AtomicBool>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, sqlx::FromRow)]
 pub struct Task {
     pub id: String,
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub kind: TaskKind,
+    /// script file under `workdir/script`, only used by script tasks
+    #[serde(default)]
     pub script: String,
+    /// only used by command tasks
+    #[serde(default)]
+    pub command: Option<sqlx::types::Json<CommandSpec>>,
+    /// legacy trigger, read when `schedule` is not set
+    #[serde(default)]
     pub cron: String,
+    #[serde(default)]
+    pub schedule: Option<sqlx::types::Json<Trigger>>,
     pub name: String,
     pub remark: Option<String>,
     pub max_executions: Option<u32>,
     pub auto_start: bool,
+    /// executions so far, kept across restarts
+    #[serde(default)]
+    pub executed_count: u32,
+    /// set once `max_executions` is reached, a finished task is not started again
+    #[serde(default)]
+    pub finished: bool,
+    /// the task does not fire before this time
+    #[serde(default)]
+    pub start_at: Option<DateTime<Local>>,
+    /// the task does not fire after this time
+    #[serde(default)]
+    pub end_at: Option<DateTime<Local>>,
+    /// ids of the calendars whose dates are skipped
+    #[serde(default)]
+    pub calendars: Option<sqlx::types::Json<Vec<String>>>,
+    /// the script may list, start, stop and run other tasks
+    #[serde(default)]
+    pub manage_tasks: bool,
+    /// the output of every run is published to this topic
+    #[serde(default)]
+    pub output_topic: Option<String>,
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub output_format: OutputFormat,
+    /// the broker the output is published to, the default one when unset
+    #[serde(default)]
+    pub output_connection: Option<String>,
+    /// the runner is alive but does not fire, kept across restarts
+    #[serde(default)]
+    pub paused: bool,
+    /// the fire the runner is waiting for, later fires of an interval trigger count from it.
+    /// Saved while paused, the pending fire survives a restart
+    #[serde(skip)]
+    pub next_fire: Option<DateTime<Local>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskKind {
+    #[default]
+    Script,
+    Command,
+}
+
+impl TaskKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskKind::Script => "script",
+            TaskKind::Command => "command",
+        }
+    }
+}
+
+impl TryFrom<String> for TaskKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "script" => Ok(TaskKind::Script),
+            "command" => Ok(TaskKind::Command),
+            _ => Err(anyhow::anyhow!("Unknown task kind: {}", value)),
+        }
+    }
+}
+
+/// How the output of a run is published
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// strings as markdown, anything else as json
+    #[default]
+    Auto,
+    Json,
+    Markdown,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Auto => "auto",
+            OutputFormat::Json => "json",
+            OutputFormat::Markdown => "markdown",
+        }
+    }
+}
+
+impl TryFrom<String> for OutputFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "auto" => Ok(OutputFormat::Auto),
+            "json" => Ok(OutputFormat::Json),
+            "markdown" => Ok(OutputFormat::Markdown),
+            _ => Err(anyhow::anyhow!("Unknown output format: {}", value)),
+        }
+    }
+}
+
+/// One execution of a task
+#[derive(Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct TaskRun {
+    pub id: String,
+    pub task_id: String,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    /// stdout of a command or the return value of a script
+    pub output: Option<String>,
+    /// stderr of a command or the error of a script
+    pub error: Option<String>,
+}
+
+/// Most fire times listed per task by `TaskManager::upcoming_runs`
+const MAX_UPCOMING_RUNS: usize = 500;
+
+/// Fire times looked at before giving up on finding one outside the calendars
+const MAX_SCANNED_FIRES: usize = 10_000;
+
+/// A predicted execution of a task
+#[derive(Serialize, Clone, Debug)]
+pub struct UpcomingRun {
+    pub task_id: String,
+    pub task: String,
+    pub at: DateTime<Local>,
 }
 
 impl TaskManager {
     pub fn new(
         send_msg_tx: tokio::sync::mpsc::UnboundedSender<crate::message::SendMessage>,
+        rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
     ) -> Self {
+        let pool_size = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
         Self {
             send_msg_tx,
+            rec_msg_tx,
+            runtime: Handle::current(),
+            pool: LocalPoolHandle::new(pool_size),
             inner: Arc::new(Mutex::new(LinkedHashMap::new())),
         }
     }
 
     pub async fn start(&self) -> anyhow::Result<()> {
         let tasks = Task::get_all_task().await?;
-        for task in tasks.into_iter().filter(|task| task.auto_start) {
-            
-            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-
-            let runner = TaskRunner::new(task.clone(), Some(tx));
-
+        for task in tasks
+            .into_iter()
+            .filter(|task| (task.auto_start || task.paused) && !task.finished)
+        {
             let log_id = task.id.clone();
             let log_task = task.clone();
 
+            let mut runner = TaskRunner::new(task.clone(), None);
+            self.spawn_task(&mut runner);
+
             self.inner.lock().insert(task.id.clone(), runner);
-            let lua = get_lua(self.send_msg_tx.clone());
-            tokio::spawn(async move {
-                tokio::select! {
-                result = task.run(lua) => {
-                    match result {
-                        Ok(_) => {
-                            info!("{} auto stop", task.id)
-                        },
-                        Err(e) => {
-                            println!("{}", e);
-                            error!("{} auto stop, error: {}", task.id, e)
-                        }
-                    }
-                },
-                _ = rx => {
-                    info!("{} manual stop", task.id)
-                },
-                }
-            });
             info!(
                 "Task {}-[{}]:{} ---- starting",
                 log_id, log_task.script, log_task.name
@@ -90,42 +245,23 @@ impl TaskManager {
         Ok(())
     }
 
-    pub async fn add_task(&self, task: Task) -> anyhow::Result<String> {
+    pub async fn add_task(&self, mut task: Task) -> anyhow::Result<String> {
+        task.check()?;
+        task.executed_count = 0;
+        task.finished = false;
+        task.paused = false;
         let id = task.insert().await?;
-
-        let id_c = id.clone();
+        task.id = id.clone();
 
         let mut runner = TaskRunner::new(task.clone(), None);
 
-        if task.clone().auto_start {
-            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-            let lua = get_lua(self.send_msg_tx.clone());
-            tokio::spawn(async move {
-                tokio::select! {
-                    result = task.run(lua) => {
-                        match result {
-                            Ok(_) => {
-                                info!("{} auto stop", id)
-                            },
-                            Err(e) => {
-                                println!("{}", e);
-                                error!("{} auto stop, error: {}", id, e)
-                            }
-                        }
-                    },
-                    _ = rx => {
-                        info!("{} manual stop", id)
-                    },
-                }
-            });
-            runner.tx = Some(tx);
-        } else {
-            runner.tx = None;
+        if task.auto_start {
+            self.spawn_task(&mut runner);
         }
 
-        self.inner.lock().insert(id_c.clone(), runner);
+        self.inner.lock().insert(id.clone(), runner);
 
-        Ok(id_c)
+        Ok(id)
     }
 
     pub fn start_task(&self, id: &String) -> anyhow::Result<()> {
@@ -138,39 +274,37 @@ impl TaskManager {
             return Err(anyhow::anyhow!("Task is running, please stop it first"));
         }
 
-        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        if runner.task.lock().finished {
+            return Err(anyhow::anyhow!(
+                "Task has reached max executions, raise it to start the task again"
+            ));
+        }
 
-        if runner.tx.is_none() {
-            runner.tx = Some(tx);
-            let task = runner.task.clone();
-            let id = id.clone();
+        self.spawn_task(runner);
 
-            let lua = get_lua(self.send_msg_tx.clone());
-            tokio::spawn(async move {
-                tokio::select! {
-                result = task.run(lua) => {
-                    match result {
-                        Ok(_) => {
-                            info!("{} auto stop", id)
-                        },
-                        Err(e) => {
-                            println!("{}", e);
-                            error!("{} auto stop, error: {}", id, e)
-                        }
+        Ok(())
+    }
+
+    /// Stop the task and drop its state, a paused task is no longer paused
+    pub fn stop_task(&self, id: &String) -> anyhow::Result<()> {
+        self.stop_runner(id)?;
+
+        let shared = self.inner.lock().get(id).map(|runner| runner.task.clone());
+        if let Some(shared) = shared {
+            if std::mem::take(&mut shared.lock().paused) {
+                let id = id.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Task::set_paused(&id, false).await {
+                        error!("{} unable to save paused state: {}", id, e);
                     }
-                },
-                _ = rx => {
-                    info!("{} manual stop", id)
-                },
-                }
-            });
-        } else {
-            return Err(anyhow::anyhow!("Task is running, please stop it first"));
+                });
+            }
         }
         Ok(())
     }
 
-    pub fn stop_task(&self, id: &String) -> anyhow::Result<()> {
+    /// Stop the running loop, the stored state of the task is left as is
+    fn stop_runner(&self, id: &String) -> anyhow::Result<()> {
         let mut runner = self.inner.lock();
         let runner = runner
             .get_mut(id)
@@ -187,19 +321,29 @@ impl TaskManager {
         Ok(())
     }
 
-    pub async fn update_task(&self, id: &String, task: Task) -> anyhow::Result<()> {
+    pub async fn update_task(&self, id: &String, mut task: Task) -> anyhow::Result<()> {
+        task.check()?;
+        task.id = id.clone();
+        if let Ok(old) = self.get_task(id) {
+            if old.name != task.name {
+                crate::metrics::remove_task(&old);
+            }
+            task.executed_count = old.executed_count;
+            task.paused = old.paused;
+        }
+        task.finished = task.remaining_executions() == Some(0);
         task.update(id).await?;
         let running = self.get_running_status(id);
 
         if running {
-            self.stop_task(id)?;
+            self.stop_runner(id)?;
         }
 
         self.inner
             .lock()
             .insert(id.to_string(), TaskRunner::new(task.clone(), None));
 
-        if running {
+        if running && !task.finished {
             self.start_task(id)?;
         }
 
@@ -208,7 +352,9 @@ impl TaskManager {
 
     pub async fn remove_task(&self, id: &String) -> anyhow::Result<()> {
         self.stop_task(id)?;
-        self.inner.lock().remove(id);
+        if let Some(runner) = self.inner.lock().remove(id) {
+            crate::metrics::remove_task(&runner.task.lock());
+        }
 
         Task::remove(&String::from(id)).await?;
         Ok(())
@@ -220,22 +366,112 @@ impl TaskManager {
         Ok(())
     }
 
+    /// Suspend a running task, its script state and execution count are kept
+    pub async fn pause_task(&self, id: &String) -> anyhow::Result<()> {
+        self.set_paused(id, true).await
+    }
+
+    pub async fn resume_task(&self, id: &String) -> anyhow::Result<()> {
+        self.set_paused(id, false).await
+    }
+
+    async fn set_paused(&self, id: &String, paused: bool) -> anyhow::Result<()> {
+        let (tx, shared) = {
+            let runner = self.inner.lock();
+            let runner = runner.get(id).ok_or(anyhow::anyhow!("Task Not Found"))?;
+            let tx = runner.tx.clone().ok_or(anyhow::anyhow!(
+                "Task is not running, please start it first"
+            ))?;
+            (tx, runner.task.clone())
+        };
+
+        if shared.lock().paused == paused {
+            return Ok(());
+        }
+
+        Task::set_paused(id, paused).await?;
+        shared.lock().paused = paused;
+        let command = if paused {
+            TaskCommand::Pause
+        } else {
+            TaskCommand::Resume
+        };
+        // the runner may already have finished on its own
+        let _ = tx.send(command);
+        Ok(())
+    }
+
+    /// Execute a running task right away, `params` are handed to the script
+    pub fn run_now(&self, id: &String, params: Option<serde_json::Value>) -> anyhow::Result<()> {
+        let runner = self.inner.lock();
+        let runner = runner.get(id).ok_or(anyhow::anyhow!("Task Not Found"))?;
+
+        if runner.task.lock().paused {
+            return Err(anyhow::anyhow!("Task is paused, please resume it first"));
+        }
+
+        runner
+            .tx
+            .as_ref()
+            .and_then(|tx| tx.send(TaskCommand::RunNow(params)).ok())
+            .ok_or(anyhow::anyhow!(
+                "Task is not running, please start it first"
+            ))
+    }
+
+    /// Run the script tests on the script pool, lua states are not `Send`
+    pub async fn run_script_tests(
+        &self,
+        filter: Option<String>,
+    ) -> anyhow::Result<Vec<script::test_runner::TestReport>> {
+        self.pool
+            .spawn_pinned(move || script::test_runner::run(filter))
+            .await?
+    }
+
     pub fn get_task(&self, id: &String) -> anyhow::Result<Task> {
         let mut runner = self.inner.lock();
         let runner = runner
             .get_mut(id)
             .ok_or(anyhow::anyhow!("Task Not Found"))?;
-        Ok(runner.task.clone())
+        let task = runner.task.lock().clone();
+        Ok(task)
     }
 
     pub fn get_all_task(&self) -> anyhow::Result<Vec<(bool, Task)>> {
         let mut tasks = Vec::new();
         for (_, runner) in self.inner.lock().deref().iter() {
-            tasks.push((runner.tx.is_some(), runner.task.clone()));
+            tasks.push((runner.tx.is_some(), runner.task.lock().clone()));
         }
         Ok(tasks)
     }
 
+    /// Fire times of the running tasks in `from..=to`, earliest first
+    pub async fn upcoming_runs(
+        &self,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> anyhow::Result<Vec<UpcomingRun>> {
+        let mut runs = Vec::new();
+        for (running, task) in self.get_all_task()? {
+            if !running || task.paused {
+                continue;
+            }
+            for at in task
+                .upcoming_runs(from, Some(to), MAX_UPCOMING_RUNS)
+                .await?
+            {
+                runs.push(UpcomingRun {
+                    task_id: task.id.clone(),
+                    task: task.name.clone(),
+                    at,
+                });
+            }
+        }
+        runs.sort_by_key(|run| run.at);
+        Ok(runs)
+    }
+
     pub fn get_running_status(&self, id: &String) -> bool {
         self.inner
             .lock()
@@ -243,16 +479,56 @@ impl TaskManager {
             .get(id)
             .is_some_and(|f| f.tx.is_some())
     }
+
+    /// Spawn the task of the runner on the script pool, the runner then controls it
+    fn spawn_task(&self, runner: &mut TaskRunner) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let shared = runner.task.clone();
+        let task = shared.lock().clone();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let host = ScriptHost::new(
+            task.name.clone(),
+            self.send_msg_tx.clone(),
+            self.rec_msg_tx.clone(),
+            task.manage_tasks.then(|| self.clone()),
+            self.runtime.clone(),
+            interrupt.clone(),
+        );
+
+        self.pool.spawn_pinned(move || async move {
+            match task.run(host, rx, shared).await {
+                Ok(TaskExit::Finished) => {
+                    info!("{} auto stop", task.id)
+                }
+                Ok(TaskExit::Stopped) => {
+                    info!("{} manual stop", task.id)
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    error!("{} auto stop, error: {}", task.id, e)
+                }
+            }
+        });
+
+        runner.tx = Some(tx);
+        runner.interrupt = interrupt;
+    }
 }
 
 impl TaskRunner {
-    fn new(task: Task, tx: Option<Sender<()>>) -> Self {
-        Self { tx, task }
+    fn new(task: Task, tx: Option<UnboundedSender<TaskCommand>>) -> Self {
+        Self {
+            tx,
+            task: Arc::new(Mutex::new(task)),
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
     }
 
     fn stop(&mut self) -> anyhow::Result<()> {
         if let Some(tx) = self.tx.take() {
-            tx.send(()).unwrap();
+            // the runner may already have finished on its own
+            let _ = tx.send(TaskCommand::Stop);
+            self.interrupt.store(true, Ordering::Relaxed);
         }
 
         Ok(())
@@ -260,175 +536,590 @@ impl TaskRunner {
 }
 
 impl Task {
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.trigger().check()?;
+
+        if let (Some(start_at), Some(end_at)) = (self.start_at, self.end_at) {
+            if start_at >= end_at {
+                anyhow::bail!("start_at must be before end_at");
+            }
+        }
+
+        match self.kind {
+            TaskKind::Script => {
+                let extension = Path::new(&self.script)
+                    .extension()
+                    .and_then(|ex| ex.to_str())
+                    .unwrap_or_default();
+                if !SCRIPT_EXTENSIONS.contains(&extension) {
+                    anyhow::bail!("Unsupported script type: {}", self.script);
+                }
+            }
+            TaskKind::Command => {
+                self.command
+                    .as_ref()
+                    .context("Command task without command")?
+                    .check()?;
+            }
+        }
+
+        if let Some(connection) = &self.output_connection {
+            let known = crate::tyme_config
+                .lock()
+                .connections()
+                .iter()
+                .any(|broker| &broker.name == connection);
+            if !known {
+                anyhow::bail!("Unknown broker connection: {}", connection);
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` when the task has no `max_executions`
+    pub fn remaining_executions(&self) -> Option<u32> {
+        self.max_executions
+            .map(|max_executions| max_executions.saturating_sub(self.executed_count))
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        match &self.schedule {
+            Some(schedule) => schedule.0.clone(),
+            None => Trigger::detect(&self.cron),
+        }
+    }
+
+    /// Publish the output of a successful script run, or of every command run,
+    /// when the task has an `output_topic`
+    fn publish_output(&self, host: &ScriptHost, output: RunOutput) -> anyhow::Result<()> {
+        let Some(topic) = &self.output_topic else {
+            return Ok(());
+        };
+
+        let (message_type, raw) = match (output, self.output_format) {
+            (RunOutput::Script(None), _) => return Ok(()),
+            (RunOutput::Script(Some(value)), OutputFormat::Json) => {
+                ("application/json", value.to_string())
+            }
+            (RunOutput::Script(Some(serde_json::Value::String(value))), _) => {
+                ("text/markdown", value)
+            }
+            (RunOutput::Script(Some(value)), OutputFormat::Markdown) => (
+                "text/markdown",
+                format!("```json\n{}\n```", serde_json::to_string_pretty(&value)?),
+            ),
+            (RunOutput::Script(Some(value)), OutputFormat::Auto) => {
+                ("application/json", value.to_string())
+            }
+            (RunOutput::Command(output), OutputFormat::Json) => {
+                ("application/json", serde_json::to_string(&output)?)
+            }
+            (RunOutput::Command(output), _) => ("text/markdown", output.to_markdown(&self.name)),
+        };
+
+        host.publish(
+            topic.clone(),
+            1,
+            false,
+            message_type,
+            raw,
+            self.output_connection.clone(),
+        )
+    }
+
+    /// The next fire time inside the active window, `None` once the window is over
+    fn next_fire(
+        &self,
+        trigger: &Trigger,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<Option<DateTime<Local>>> {
+        Ok(self.fire_times(trigger, now)?.next())
+    }
+
+    /// The fire following `last` as the runner plans it. It counts from `last` rather than from
+    /// the end of the run, fires missed meanwhile are skipped
+    fn fire_after(
+        &self,
+        trigger: &Trigger,
+        last: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<Option<DateTime<Local>>> {
+        self.next_fire(trigger, resume_point(trigger, last, now))
+    }
+
+    /// Fire times after `now` within the active window of the task
+    fn fire_times(
+        &self,
+        trigger: &Trigger,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<impl Iterator<Item = DateTime<Local>> + Send> {
+        let from = match self.start_at {
+            Some(start_at) if start_at > now => start_at,
+            _ => now,
+        };
+
+        let end_at = self.end_at;
+        Ok(trigger
+            .upcoming(from)?
+            .take_while(move |next| match end_at {
+                Some(end_at) => *next <= end_at,
+                None => true,
+            }))
+    }
+
+    /// Fire times from `from` on as the runner will see them.
+    ///
+    /// Interval triggers count from the fire the runner waits for, not from `from`, the runner
+    /// counts the same way unless a run outlasts the interval
+    fn planned_fire_times(
+        &self,
+        trigger: &Trigger,
+        from: DateTime<Local>,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = DateTime<Local>> + Send>> {
+        let Some(next) = self.next_fire.filter(|next| *next >= Local::now()) else {
+            return Ok(Box::new(self.fire_times(trigger, from)?));
+        };
+
+        // skip the whole intervals before `from` instead of walking through them
+        let start = match trigger {
+            Trigger::Interval { .. } => resume_point(trigger, next, from),
+            _ => next,
+        };
+
+        let end_at = self.end_at;
+        Ok(Box::new(
+            std::iter::once(start)
+                .chain(trigger.upcoming(start)?)
+                .skip_while(move |at| *at < from)
+                .take_while(move |at| match end_at {
+                    Some(end_at) => *at <= end_at,
+                    None => true,
+                }),
+        ))
+    }
+
+    /// The next `limit` executions after `from`, none after `to`.
+    ///
+    /// Dates excluded by a calendar are skipped and the remaining executions are honoured,
+    /// for interval triggers with a jitter the times are an estimate
+    pub async fn upcoming_runs(
+        &self,
+        from: DateTime<Local>,
+        to: Option<DateTime<Local>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<DateTime<Local>>> {
+        let limit = match self.remaining_executions() {
+            Some(remaining) => limit.min(remaining as usize),
+            None => limit,
+        };
+
+        let mut excluded = HashMap::new();
+        let mut runs = Vec::new();
+        for at in self
+            .planned_fire_times(&self.trigger(), from)?
+            .take(MAX_SCANNED_FIRES)
+        {
+            let past_window = match to {
+                Some(to) => at > to,
+                None => false,
+            };
+            if runs.len() >= limit || past_window {
+                break;
+            }
+
+            let date = at.date_naive();
+            let is_excluded = match excluded.get(&date) {
+                Some(is_excluded) => *is_excluded,
+                None => {
+                    let is_excluded = self.is_excluded(date).await;
+                    excluded.insert(date, is_excluded);
+                    is_excluded
+                }
+            };
+            if !is_excluded {
+                runs.push(at);
+            }
+        }
+        Ok(runs)
+    }
+
+    /// Whether one of the task calendars excludes `date`
+    async fn is_excluded(&self, date: NaiveDate) -> bool {
+        let Some(calendars) = &self.calendars else {
+            return false;
+        };
+
+        for calendar_id in calendars.iter() {
+            match Calendar::excludes(calendar_id, date).await {
+                Ok(true) => return true,
+                Ok(false) => {}
+                // rather fire than silently stop the task
+                Err(e) => error!("{} unable to read calendar {}: {}", self.id, calendar_id, e),
+            }
+        }
+        false
+    }
+
     /// 执行脚本
-    /// let _ = script.exec().unwrap();
-    /// let _ = script.call::<_, mlua::Value>(()).unwrap();
-    /// let _ = script.eval::<mlua::Value>().unwrap();
-    pub async fn run(&self, lua: Lua) -> anyhow::Result<()> {
-        let schedule = Schedule::from_str(self.cron.as_str()).unwrap();
-
-        let script_path = crate::start_param
-            .word_dir
-            .clone()
-            .join("script")
-            .join(&self.script);
-
-        let script_content = tokio::fs::read_to_string(script_path).await?;
-
-        if let Some(max_executions) = self.max_executions {
-            for _ in 0..max_executions {
-                let now = chrono::offset::Local::now();
-                let next = schedule
-                    .upcoming(chrono::offset::Local)
-                    .next()
-                    .context("No upcoming dates")?;
-                let duration = (next - now).to_std()?;
-                tokio::time::sleep(duration).await;
-                let script = lua.load(script_content.clone());
-                script.exec()?;
+    ///
+    /// `shared` is the copy held by the `TaskManager`, its execution count is kept in sync
+    pub async fn run(
+        &self,
+        host: ScriptHost,
+        mut control_rx: UnboundedReceiver<TaskCommand>,
+        shared: Arc<Mutex<Task>>,
+    ) -> anyhow::Result<TaskExit> {
+        let trigger = self.trigger();
+
+        let mut executor = match self.kind {
+            TaskKind::Script => {
+                let script_path = crate::start_param
+                    .word_dir
+                    .clone()
+                    .join("script")
+                    .join(&self.script);
+
+                let script_content = tokio::fs::read_to_string(script_path).await?;
+
+                Executor::Script(script::load(self, host.clone(), script_content)?)
             }
-        } else {
+            TaskKind::Command => Executor::Command(
+                self.command
+                    .as_ref()
+                    .context("Command task without command")?
+                    .0
+                    .clone(),
+            ),
+        };
+        let mut executions = self.executed_count;
+        let mut paused = self.paused;
+        // the fire waited for, kept across pauses and `run_now`
+        let mut planned = self.next_fire.filter(|_| self.paused);
+        // the last fire waited for, the following one counts from it
+        let mut last_fire: Option<DateTime<Local>> = None;
+
+        // `on_start` and `on_message` do not wait for the first fire
+        let started = {
+            let start = executor.start();
+            tokio::pin!(start);
+
             loop {
-                let now = chrono::offset::Local::now();
-                let next = schedule
-                    .upcoming(chrono::offset::Local)
-                    .next()
-                    .context("No upcoming dates")?;
-                let duration = (next - now).to_std()?;
-                tokio::time::sleep(duration).await;
-                let script = lua.load(script_content.clone());
-                script.exec()?;
+                tokio::select! {
+                    result = &mut start => break Some(result),
+                    command = control_rx.recv() => match command {
+                        Some(TaskCommand::RunNow(_)) => {
+                            info!("{} is still starting, run now ignored", self.id)
+                        }
+                        Some(TaskCommand::Pause) => paused = true,
+                        Some(TaskCommand::Resume) => paused = false,
+                        Some(TaskCommand::Stop) | None => break None,
+                    },
+                }
+            }
+        };
+        match started {
+            Some(Ok(())) => {}
+            Some(Err(_)) if host.interrupted() => return Ok(TaskExit::Stopped),
+            Some(Err(e)) => return Err(e),
+            None => {
+                executor.stop().await?;
+                return Ok(TaskExit::Stopped);
             }
         }
-        Ok(())
+        let mut rec_msg_rx: Option<broadcast::Receiver<(Header, RecMessage)>> =
+            executor.wants_messages().then(|| host.subscribe());
+
+        'task: loop {
+            if let Some(max_executions) = self.max_executions {
+                if executions >= max_executions {
+                    break;
+                }
+            }
+
+            let now = chrono::offset::Local::now();
+            let next = match planned {
+                Some(next) => next,
+                None => {
+                    let next = match last_fire {
+                        Some(last_fire) => self.fire_after(&trigger, last_fire, now)?,
+                        None => self.next_fire(&trigger, now)?,
+                    };
+                    let Some(next) = next else {
+                        info!("{} is past its end", self.id);
+                        executor.stop().await?;
+                        return Ok(TaskExit::Finished);
+                    };
+                    next
+                }
+            };
+            planned = Some(next);
+            shared.lock().next_fire = Some(next);
+
+            if paused {
+                if let Err(e) = Task::set_next_fire(&self.id, planned).await {
+                    error!("{} unable to save next fire: {}", self.id, e);
+                }
+                match control_rx.recv().await {
+                    Some(TaskCommand::Resume) => paused = false,
+                    Some(TaskCommand::Pause) => {}
+                    Some(TaskCommand::RunNow(_)) => info!("{} is paused, run now ignored", self.id),
+                    Some(TaskCommand::Stop) | None => {
+                        executor.stop().await?;
+                        return Ok(TaskExit::Stopped);
+                    }
+                }
+                continue;
+            }
+
+            // a fire missed while paused runs at once
+            let duration = (next - now).to_std().unwrap_or_default();
+            let sleep = tokio::time::sleep(duration);
+            tokio::pin!(sleep);
+
+            // `Some` when the run was requested through `run_now`
+            let run_now = loop {
+                tokio::select! {
+                    _ = &mut sleep => {
+                        planned = None;
+                        last_fire = Some(next);
+                        break None;
+                    }
+                    msg = recv_message(&mut rec_msg_rx) => {
+                        match msg {
+                            Ok((_, msg)) => {
+                                if let Err(e) = executor.on_message(msg).await {
+                                    if host.interrupted() {
+                                        return Ok(TaskExit::Stopped);
+                                    }
+                                    return Err(e);
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Lagged(_)) => {}
+                            Err(broadcast::error::RecvError::Closed) => rec_msg_rx = None,
+                        }
+                    },
+                    command = control_rx.recv() => match command {
+                        Some(TaskCommand::RunNow(params)) => break Some(params),
+                        Some(TaskCommand::Pause) => {
+                            paused = true;
+                            continue 'task;
+                        }
+                        Some(TaskCommand::Resume) => {}
+                        Some(TaskCommand::Stop) | None => {
+                            executor.stop().await?;
+                            return Ok(TaskExit::Stopped);
+                        }
+                    },
+                }
+            };
+
+            if run_now.is_none() && self.is_excluded(next.date_naive()).await {
+                info!("{} skipped on {}, excluded by calendar", self.id, next);
+                continue;
+            }
+
+            let started_at = Local::now();
+            let result = {
+                let tick = executor.tick(run_now.flatten());
+                tokio::pin!(tick);
+
+                loop {
+                    tokio::select! {
+                        result = &mut tick => break Some(result),
+                        command = control_rx.recv() => match command {
+                            Some(TaskCommand::RunNow(_)) => {
+                                info!("{} is already running, run now ignored", self.id)
+                            }
+                            // takes effect once the current run is over
+                            Some(TaskCommand::Pause) => paused = true,
+                            Some(TaskCommand::Resume) => paused = false,
+                            Some(TaskCommand::Stop) | None => break None,
+                        },
+                    }
+                }
+            };
+            let Some(result) = result else {
+                executor.stop().await?;
+                return Ok(TaskExit::Stopped);
+            };
+            // a script interrupted by the stop is left as it was, without `on_stop`
+            if result.is_err() && host.interrupted() {
+                return Ok(TaskExit::Stopped);
+            }
+            executions += 1;
+            shared.lock().executed_count = executions;
+            if let Err(e) = Task::increment_executed_count(&self.id).await {
+                error!("{} unable to save execution count: {}", self.id, e);
+            }
+
+            let mut run = TaskRun {
+                id: String::new(),
+                task_id: self.id.clone(),
+                started_at,
+                finished_at: Local::now(),
+                success: false,
+                exit_code: None,
+                output: None,
+                error: None,
+            };
+            // a failed command only fails this run, a failed script stops the task
+            let script_error = match result {
+                Ok(output) => {
+                    match &output {
+                        RunOutput::Command(output) => {
+                            run.success = output.success();
+                            run.exit_code = output.exit_code;
+                            run.output = Some(output.stdout.clone()).filter(|s| !s.is_empty());
+                            run.error = Some(output.stderr.clone()).filter(|s| !s.is_empty());
+                        }
+                        RunOutput::Script(value) => {
+                            run.success = true;
+                            run.output = value.as_ref().map(|value| match value {
+                                serde_json::Value::String(value) => value.clone(),
+                                value => value.to_string(),
+                            });
+                        }
+                    }
+                    if let Err(e) = self.publish_output(&host, output) {
+                        error!("{} unable to publish output: {}", self.id, e);
+                        run.error = Some(format!("Unable to publish output: {}", e));
+                    }
+                    None
+                }
+                Err(e) => {
+                    run.error = Some(e.to_string());
+                    executor.is_script().then_some(e)
+                }
+            };
+            crate::metrics::task_run(self, &run);
+            if let Err(e) = run.insert().await {
+                error!("{} unable to save run history: {}", self.id, e);
+            }
+            if let Some(e) = script_error {
+                return Err(e);
+            }
+
+            if rec_msg_rx.is_none() && executor.wants_messages() {
+                rec_msg_rx = Some(host.subscribe());
+            }
+        }
+
+        // only reached once max_executions is exhausted
+        {
+            let mut shared = shared.lock();
+            shared.finished = true;
+            shared.auto_start = false;
+        }
+        if let Err(e) = Task::finish(&self.id).await {
+            error!("{} unable to mark as finished: {}", self.id, e);
+        }
+
+        executor.stop().await?;
+        Ok(TaskExit::Finished)
     }
 }
 
-struct TymeUserData {
-    send_msg_tx: tokio::sync::mpsc::UnboundedSender<crate::message::SendMessage>,
+/// Where the fires after `last` are counted from once `now` is reached: interval triggers skip
+/// the whole intervals missed meanwhile, the others continue from `now`
+fn resume_point(trigger: &Trigger, last: DateTime<Local>, now: DateTime<Local>) -> DateTime<Local> {
+    match trigger {
+        Trigger::Interval { seconds, .. } if now > last => {
+            let skipped = (now - last).num_seconds().max(0) as u64 / seconds;
+            last + std::time::Duration::from_secs(skipped * seconds)
+        }
+        Trigger::Interval { .. } => last,
+        _ => last.max(now),
+    }
 }
 
-impl mlua::UserData for TymeUserData {
-    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
-        fields.add_field_method_get("sys_config", get_sys_config);
+enum RunOutput {
+    /// the value returned by the script, if any
+    Script(Option<serde_json::Value>),
+    Command(CommandOutput),
+}
+
+/// What a task runs on every tick
+enum Executor {
+    Script(Box<dyn ScriptEngine>),
+    Command(CommandSpec),
+}
+
+impl Executor {
+    fn is_script(&self) -> bool {
+        matches!(self, Executor::Script(_))
+    }
+
+    /// Commands receive `params` as json in the `TYME_PARAMS` environment variable
+    async fn tick(&mut self, params: Option<serde_json::Value>) -> anyhow::Result<RunOutput> {
+        match self {
+            Executor::Script(script) => Ok(RunOutput::Script(script.tick(params).await?)),
+            Executor::Command(command) => {
+                let output = match params {
+                    Some(params) => {
+                        let mut command = command.clone();
+                        command
+                            .env
+                            .insert("TYME_PARAMS".to_string(), params.to_string());
+                        command.run().await?
+                    }
+                    None => command.run().await?,
+                };
+                Ok(RunOutput::Command(output))
+            }
+        }
+    }
+
+    async fn on_message(&mut self, msg: RecMessage) -> anyhow::Result<()> {
+        match self {
+            Executor::Script(script) => script.on_message(msg).await,
+            Executor::Command(_) => Ok(()),
+        }
     }
 
-    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
-        methods.add_async_method("send_json", lua_send_json);
-        methods.add_async_method("send_markdown", lua_send_markdown);
+    async fn start(&mut self) -> anyhow::Result<()> {
+        match self {
+            Executor::Script(script) => script.start().await,
+            Executor::Command(_) => Ok(()),
+        }
     }
-}
 
-fn get_sys_config(_: &mlua::Lua, _: &TymeUserData) -> mlua::Result<TymeConfig> {
-    let sys_config = crate::tyme_config.lock().clone();
-    Ok(sys_config)
+    fn wants_messages(&self) -> bool {
+        match self {
+            Executor::Script(script) => script.wants_messages(),
+            Executor::Command(_) => false,
+        }
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        match self {
+            Executor::Script(script) => script.stop().await,
+            Executor::Command(_) => Ok(()),
+        }
+    }
 }
 
-async fn lua_send_json(
-    _: &mlua::Lua,
-    tyme_user_data: &TymeUserData,
-    (topic, qos, ephemeral, json): (String, i32, bool, mlua::Value<'_>),
-) -> mlua::Result<()> {
-    let json_string = serde_json::to_string(&json).unwrap();
-
-    let msg = crate::message::SendMessage {
-        topic,
-        qos,
-        retain: None,
-        receiver: None,
-        ephemeral,
-        message_type: "application/json".to_string(),
-        raw: json_string,
-    };
-    tyme_user_data.send_msg_tx.send(msg).unwrap();
-    Ok(())
+/// Sent by the `TaskManager` to a running task
+pub enum TaskCommand {
+    Stop,
+    RunNow(Option<serde_json::Value>),
+    /// stop firing, the script state is kept
+    Pause,
+    Resume,
 }
 
-async fn lua_send_markdown(
-    _: &mlua::Lua,
-    tyme_user_data: &TymeUserData,
-    (topic, qos, ephemeral, markdown): (String, i32, bool, mlua::Value<'_>),
-) -> mlua::Result<()> {
-    let markdown_string = markdown.to_string().unwrap();
-
-    let msg = crate::message::SendMessage {
-        topic,
-        qos,
-        retain: None,
-        receiver: None,
-        ephemeral,
-        message_type: "text/markdown".to_string(),
-        raw: markdown_string,
-    };
-
-    tyme_user_data.send_msg_tx.send(msg).unwrap();
-
-    Ok(())
+pub enum TaskExit {
+    /// the schedule or `max_executions` ran out
+    Finished,
+    /// stopped through the `TaskManager`
+    Stopped,
 }
 
-fn get_lua(
-    send_msg_tx: tokio::sync::mpsc::UnboundedSender<crate::message::SendMessage>,
-) -> mlua::Lua {
-    let lua = mlua::Lua::new();
-    let package_path = lua
-        .globals()
-        .get::<_, mlua::Table>("package")
-        .unwrap()
-        .get::<_, String>("path")
-        .unwrap();
-
-    let package_cpath = lua
-        .globals()
-        .get::<_, mlua::Table>("package")
-        .unwrap()
-        .get::<_, String>("cpath")
-        .unwrap();
-
-    let tyme_package_path = crate::start_param
-        .word_dir
-        .clone()
-        .join("script")
-        .join("?.lua");
-
-    let tyme_sys_package_path = std::env::current_dir().unwrap().join("?.lua");
-
-    #[cfg(target_os = "windows")]
-    let tyme_package_cpath = crate::start_param
-        .word_dir
-        .clone()
-        .join("script")
-        .join("?.dll");
-
-    #[cfg(not(target_os = "windows"))]
-    let tyme_package_cpath = crate::start_param
-        .word_dir
-        .clone()
-        .join("script")
-        .join("?.so");
-
-    let package_path = format!(
-        "{};{};{}",
-        package_path,
-        tyme_sys_package_path.display(),
-        tyme_package_path.display()
-    );
-    let package_cpath = format!("{};{}", package_cpath, tyme_package_cpath.display());
-
-    lua.globals()
-        .get::<_, mlua::Table>("package")
-        .unwrap()
-        .set("path", package_path)
-        .unwrap();
-
-    lua.globals()
-        .get::<_, mlua::Table>("package")
-        .unwrap()
-        .set("cpath", package_cpath)
-        .unwrap();
-
-    let tyme_user_data = TymeUserData { send_msg_tx };
-
-    lua.globals().set("tyme_sys", tyme_user_data).unwrap();
-
-    lua
+/// Pending forever while there is nothing to listen to
+async fn recv_message(
+    rec_msg_rx: &mut Option<broadcast::Receiver<(Header, RecMessage)>>,
+) -> Result<(Header, RecMessage), broadcast::error::RecvError> {
+    match rec_msg_rx {
+        Some(rec_msg_rx) => rec_msg_rx.recv().await,
+        None => std::future::pending().await,
+    }
 }
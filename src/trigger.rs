@@ -0,0 +1,194 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// When a task fires
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// classic 5-field crontab: `minute hour day-of-month month day-of-week`
+    Crontab { expr: String },
+    /// 6 or 7 fields starting with seconds, the format of the `cron` crate
+    Cron { expr: String },
+    /// every `seconds`, each fire delayed by a random `0..=jitter` seconds
+    Interval {
+        seconds: u64,
+        #[serde(default)]
+        jitter: u64,
+    },
+    /// a single fire at `at`
+    Once { at: DateTime<Local> },
+}
+
+impl Trigger {
+    /// Read a legacy `cron` column, 5 fields are taken as crontab syntax
+    pub fn detect(expr: &str) -> Self {
+        let expr = expr.trim().to_string();
+        if expr.split_whitespace().count() == 5 {
+            Trigger::Crontab { expr }
+        } else {
+            Trigger::Cron { expr }
+        }
+    }
+
+    pub fn check(&self) -> anyhow::Result<()> {
+        match self {
+            Trigger::Crontab { .. } | Trigger::Cron { .. } => {
+                self.schedule()?;
+            }
+            Trigger::Interval { seconds, .. } => {
+                if *seconds == 0 {
+                    anyhow::bail!("interval must be at least one second");
+                }
+            }
+            Trigger::Once { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn schedule(&self) -> anyhow::Result<Option<Schedule>> {
+        let expr = match self {
+            Trigger::Crontab { expr } => crontab_to_cron(expr)?,
+            Trigger::Cron { expr } => expr.clone(),
+            _ => return Ok(None),
+        };
+        let schedule =
+            Schedule::from_str(&expr).with_context(|| format!("Invalid cron: {}", expr))?;
+        Ok(Some(schedule))
+    }
+
+    /// Iterate the fire times after `from`, `None` ends the iteration
+    pub fn upcoming(
+        &self,
+        from: DateTime<Local>,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = DateTime<Local>> + Send>> {
+        if let Some(schedule) = self.schedule()? {
+            return Ok(Box::new(schedule.after_owned(from)));
+        }
+
+        match self {
+            Trigger::Interval { seconds, jitter } => {
+                let (seconds, jitter) = (*seconds, *jitter);
+                let mut next = from;
+                Ok(Box::new(std::iter::from_fn(move || {
+                    let delay = seconds + rand::thread_rng().gen_range(0..=jitter);
+                    next += Duration::from_secs(delay);
+                    Some(next)
+                })))
+            }
+            Trigger::Once { at } => Ok(Box::new(Some(*at).filter(|at| *at > from).into_iter())),
+            _ => unreachable!("cron triggers always have a schedule"),
+        }
+    }
+}
+
+/// `0 m h dom mon dow` with the day-of-week renumbered for the `cron` crate,
+/// which counts from Sunday = 1 where crontab uses Sunday = 0 or 7.
+///
+/// Crontab fires when either the day of month or the day of week matches if both are
+/// restricted, the `cron` crate needs both to match, so such expressions are refused
+fn crontab_to_cron(expr: &str) -> anyhow::Result<String> {
+    let expr = expr.trim();
+    if expr.starts_with('@') {
+        return Ok(expr.to_string());
+    }
+
+    let fields = expr.split_whitespace().collect::<Vec<_>>();
+    let [minute, hour, dom, month, dow] = fields[..] else {
+        anyhow::bail!("Crontab expects 5 fields, got {}: {}", fields.len(), expr);
+    };
+
+    let restricted = |field: &str| !field.starts_with('*') && field != "?";
+    if restricted(dom) && restricted(dow) {
+        anyhow::bail!(
+            "Restricting both the day of month and the day of week is not supported, \
+             crontab would fire on either of them: {}",
+            expr
+        );
+    }
+
+    Ok(format!(
+        "0 {} {} {} {} {}",
+        minute,
+        hour,
+        dom,
+        month,
+        crontab_weekday(dow)?
+    ))
+}
+
+fn crontab_weekday(field: &str) -> anyhow::Result<String> {
+    // names mean the same in both syntaxes
+    if field == "*" || field.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Ok(field.to_string());
+    }
+
+    let parse = |value: &str| {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|day| *day <= 7)
+            .with_context(|| format!("Invalid day of week: {}", field))
+    };
+
+    let mut days = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>()?.max(1)),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None if range == "*" => (0, 6),
+            None if step > 1 => (parse(range)?, 6),
+            None => (parse(range)?, parse(range)?),
+        };
+
+        for day in (start..=end).step_by(step) {
+            let day = day % 7 + 1;
+            if !days.contains(&day) {
+                days.push(day);
+            }
+        }
+    }
+
+    if days.is_empty() {
+        anyhow::bail!("Invalid day of week: {}", field);
+    }
+    days.sort_unstable();
+    Ok(days
+        .into_iter()
+        .map(|day| day.to_string())
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crontab_weekdays_are_renumbered() {
+        assert_eq!(
+            crontab_to_cron("30 8 * * 1-5").unwrap(),
+            "0 30 8 * * 2,3,4,5,6"
+        );
+        assert_eq!(crontab_to_cron("0 0 * * 0").unwrap(), "0 0 0 * * 1");
+        assert_eq!(crontab_to_cron("0 0 * * 7").unwrap(), "0 0 0 * * 1");
+        assert_eq!(crontab_to_cron("0 0 * * 5-7").unwrap(), "0 0 0 * * 1,6,7");
+        assert_eq!(crontab_to_cron("0 0 * * */2").unwrap(), "0 0 0 * * 1,3,5,7");
+        assert_eq!(crontab_to_cron("0 0 * * MON").unwrap(), "0 0 0 * * MON");
+        assert!(crontab_to_cron("0 0 * * 8").is_err());
+    }
+
+    #[test]
+    fn crontab_day_of_month_or_week_is_refused() {
+        assert!(crontab_to_cron("0 0 1 * 1").is_err());
+        assert_eq!(crontab_to_cron("0 0 1 * *").unwrap(), "0 0 0 1 * *");
+        assert_eq!(crontab_to_cron("0 0 */2 * 1").unwrap(), "0 0 0 */2 * 2");
+    }
+}
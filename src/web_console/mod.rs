@@ -9,7 +9,7 @@ use tokio::sync::{
 use tower_sessions::{MemoryStore, SessionManagerLayer};
 
 use crate::{
-    header::Header,
+    header::{Header, HeaderCommand},
     message::{RecMessage, SendMessage},
 };
 pub use routes::PageParam;
@@ -43,7 +43,7 @@ pub async fn run_guide_web_console() -> anyhow::Result<()> {
 
 pub async fn run_web_console(
     send_msg_tx: UnboundedSender<SendMessage>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
     task_manager: crate::TaskManager,
 ) -> anyhow::Result<()> {
@@ -0,0 +1,68 @@
+use axum::{extract::Path, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::calendar::{Calendar, CalendarEntry};
+
+pub async fn get_all_calendar() -> impl IntoResponse {
+    match Calendar::get_all_calendar().await {
+        Ok(calendars) => Json(json!({"result": "ok", "calendars": calendars})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn add_calendar(Json(calendar): Json<Calendar>) -> impl IntoResponse {
+    let result = async {
+        calendar.check()?;
+        calendar.insert().await
+    };
+
+    match result.await {
+        Ok(id) => Json(json!({"result": "ok","id":id})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn update_calendar(
+    Path(id): Path<String>,
+    Json(calendar): Json<Calendar>,
+) -> impl IntoResponse {
+    let result = async {
+        calendar.check()?;
+        calendar.update(&id).await
+    };
+
+    match result.await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn remove_calendar(Path(id): Path<String>) -> impl IntoResponse {
+    match Calendar::remove(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn add_calendar_entry(
+    Path(id): Path<String>,
+    Json(mut entry): Json<CalendarEntry>,
+) -> impl IntoResponse {
+    entry.calendar_id = id;
+    let result = async {
+        entry.check()?;
+        entry.insert().await
+    };
+
+    match result.await {
+        Ok(id) => Json(json!({"result": "ok","id":id})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn remove_calendar_entry(Path(id): Path<String>) -> impl IntoResponse {
+    match CalendarEntry::remove(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
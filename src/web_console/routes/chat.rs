@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, ops::ControlFlow};
+use std::{net::SocketAddr, ops::ControlFlow, time::Duration};
 
 use askama::Template;
 use axum::{
@@ -6,7 +6,7 @@ use axum::{
         ws::{Message as wsMessage, WebSocket},
         ConnectInfo, Path, Query, State, WebSocketUpgrade,
     },
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json, TypedHeader,
 };
@@ -20,8 +20,11 @@ use tokio::sync::{
 };
 use tower_sessions::Session;
 
+use anyhow::Context;
+
 use crate::{
-    header::Header,
+    delivery::{self, DeliveryStatus},
+    header::{Header, HeaderCommand},
     message::{RecMessage, SendMessage},
 };
 
@@ -45,13 +48,68 @@ pub struct PageParam {
     pub page_size: usize,
 }
 
-#[allow(clippy::unused_async)]
+/// Longest wait for the broker acknowledgement, in milliseconds
+const MAX_SEND_TIMEOUT: u64 = 60_000;
+
+#[derive(Deserialize)]
+pub struct SendParams {
+    /// answer once the broker acknowledged the message, or it was queued or failed
+    #[serde(default)]
+    wait: bool,
+    /// milliseconds, the status so far is returned when it runs out
+    timeout: Option<u64>,
+}
+
 pub async fn send(
     State(send_msg_tx): State<UnboundedSender<SendMessage>>,
-    Json(msg): Json<crate::message::SendMessage>,
+    Query(params): Query<SendParams>,
+    Json(mut msg): Json<crate::message::SendMessage>,
 ) -> impl IntoResponse {
-    match send_msg_tx.send(msg.clone()) {
-        Ok(_) => Json(json!({"result": "ok", "message": "Push success"})),
+    let id = nanoid::nanoid!();
+    msg.id = Some(id.clone());
+    let events = delivery::events();
+    let accepted = delivery::accept(&id, &msg);
+
+    if let Err(e) = send_msg_tx.send(msg) {
+        delivery::update(&id, DeliveryStatus::Failed, Some(e.to_string()));
+        return Json(json!({"result": "error", "message": e.to_string()}));
+    }
+    if !params.wait {
+        return Json(
+            json!({"result": "ok", "message": "Push success", "id": id, "status": accepted.status}),
+        );
+    }
+
+    let timeout = Duration::from_millis(params.timeout.unwrap_or(10_000).min(MAX_SEND_TIMEOUT));
+    match delivery::settled(&id, events, timeout).await {
+        Ok(Some(delivery)) => match delivery.status {
+            DeliveryStatus::Dropped | DeliveryStatus::Failed => Json(json!({
+                "result": "error",
+                "message": delivery.error.clone().unwrap_or_default(),
+                "id": id,
+                "status": delivery.status,
+                "delivery": delivery,
+            })),
+            _ => Json(json!({
+                "result": "ok",
+                "message": "Push success",
+                "id": id,
+                "status": delivery.status,
+                "delivery": delivery,
+            })),
+        },
+        Ok(None) => Json(
+            json!({"result": "ok", "message": "Push success", "id": id, "status": accepted.status}),
+        ),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string(), "id": id})),
+    }
+}
+
+/// What became of a message sent through `/send`
+pub async fn send_status(Path(id): Path<String>) -> impl IntoResponse {
+    match delivery::get(&id).await {
+        Ok(Some(delivery)) => Json(json!({"result": "ok", "delivery": delivery})),
+        Ok(None) => Json(json!({"result": "error", "message": "Unknown message id"})),
         Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
     }
 }
@@ -71,13 +129,75 @@ pub async fn get_all_toppic() -> impl IntoResponse {
     }
 }
 
-#[allow(clippy::unused_async)]
+/// Save the header and subscribe to its topic
 pub async fn subscribe_topic(
-    State(sub_header_tx): State<UnboundedSender<Header>>,
-    Json(topics): Json<crate::header::Header>,
+    State(sub_header_tx): State<UnboundedSender<HeaderCommand>>,
+    Json(mut header): Json<crate::header::Header>,
 ) -> impl IntoResponse {
-    match sub_header_tx.send(topics) {
-        Ok(_) => Json(json!({"result": "ok", "message": "Push success"})),
+    let result = async {
+        header.id = String::new();
+        header.check()?;
+        header.check_unique().await?;
+        header.id = header.insert().await?;
+        sub_header_tx.send(HeaderCommand::Subscribe(header.clone()))?;
+        anyhow::Ok(header.id)
+    };
+
+    match result.await {
+        Ok(id) => Json(json!({"result": "ok", "id": id, "message": "Push success"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+/// Change the topic or QoS of a header, the subscription follows
+pub async fn update_topic(
+    Path(id): Path<String>,
+    State(sub_header_tx): State<UnboundedSender<HeaderCommand>>,
+    Json(mut header): Json<crate::header::Header>,
+) -> impl IntoResponse {
+    let result = async {
+        header.id = id.clone();
+        header.check()?;
+        header.check_unique().await?;
+        let old = Header::get_by_id(&id).await?.context("Topic not found")?;
+        header.update(&id).await?;
+
+        if old.topic != header.topic || old.connection != header.connection {
+            sub_header_tx.send(HeaderCommand::Unsubscribe(old))?;
+        }
+        // subscribing again to the same topic replaces its QoS
+        sub_header_tx.send(HeaderCommand::Subscribe(header))?;
+        anyhow::Ok(())
+    };
+
+    match result.await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RemoveTopicParams {
+    /// delete the stored messages of the topic too
+    #[serde(default)]
+    purge: bool,
+}
+
+/// Unsubscribe and remove the header, refused while it has stored messages unless `purge` is set
+pub async fn remove_topic(
+    Path(id): Path<String>,
+    State(sub_header_tx): State<UnboundedSender<HeaderCommand>>,
+    Query(params): Query<RemoveTopicParams>,
+) -> impl IntoResponse {
+    let result = async {
+        let header = Header::get_by_id(&id).await?.context("Topic not found")?;
+        Header::remove(&id, params.purge).await?;
+        sub_header_tx.send(HeaderCommand::Unsubscribe(header))?;
+        anyhow::Ok(())
+    };
+
+    match result.await {
+        Ok(_) => Json(json!({"result": "ok"})),
         Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
     }
 }
@@ -134,6 +254,30 @@ pub async fn stand_alone_message(Path(id): Path<String>) -> impl IntoResponse {
     }
 }
 
+/// The payload of a message as it was published, with its content type
+pub async fn download_message(Path(id): Path<String>) -> Response {
+    let msg = match crate::db::get_msg_by_id(&id).await {
+        Ok(Some(msg)) => msg,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Msg Not Found").into_response(),
+        Err(_) => return (StatusCode::BAD_REQUEST, "Get Error").into_response(),
+    };
+
+    match msg.content.payload() {
+        Ok(payload) => (
+            [
+                (header::CONTENT_TYPE, msg.content.message_type),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(r#"attachment; filename="{}""#, msg.id),
+                ),
+            ],
+            payload,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 #[allow(clippy::unused_async)]
 pub async fn ws_handler(
     State(rec_msg_tx): State<broadcast::Sender<(Header, RecMessage)>>,
@@ -178,9 +322,33 @@ async fn handle_socket(
 
     let (mut sink, mut stream) = socket.split();
 
+    let mut status_rx = crate::mqtt::status_events();
+    for status in crate::mqtt::status() {
+        let status = json!({"event": "mqtt_status", "status": status});
+        if sink
+            .send(wsMessage::Text(status.to_string()))
+            .await
+            .is_err()
+        {
+            info!("Error sending message to {who}");
+            return;
+        }
+    }
+
     let mut send_task = tokio::spawn(async move {
-        while let Ok((header, msg)) = rec_msg_rx.recv().await {
-            let msg = json!({"header": header, "msg": msg});
+        loop {
+            // messages carry their header, other events are named by `event`
+            let msg = tokio::select! {
+                msg = rec_msg_rx.recv() => match msg {
+                    Ok((header, msg)) => json!({"header": header, "msg": msg}),
+                    Err(_) => break,
+                },
+                status = status_rx.recv() => match status {
+                    Ok(status) => json!({"event": "mqtt_status", "status": status}),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
             let msg = serde_json::to_string(&msg).unwrap();
             let msg = wsMessage::Text(msg);
             if sink.send(msg).await.is_err() {
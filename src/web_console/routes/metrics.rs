@@ -0,0 +1,24 @@
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+
+/// Prometheus scrape endpoint
+#[allow(clippy::unused_async)]
+pub async fn handler() -> impl IntoResponse {
+    match crate::metrics::gather() {
+        Ok(metrics) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            metrics,
+        ),
+        Err(err) => {
+            log::error!("Metrics: {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                err.to_string(),
+            )
+        }
+    }
+}
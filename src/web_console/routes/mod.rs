@@ -1,8 +1,13 @@
 mod api;
 mod auth;
+mod calendar;
 mod chat;
 mod file;
+mod metrics;
+mod mqtt;
 mod notimplemented;
+mod outbox;
+mod secret;
 mod session;
 mod sys;
 mod task;
@@ -13,24 +18,47 @@ pub use auth::login;
 pub use auth::logout;
 pub use notimplemented::not_implemented_route;
 
+pub use calendar::add_calendar;
+pub use calendar::add_calendar_entry;
+pub use calendar::get_all_calendar;
+pub use calendar::remove_calendar;
+pub use calendar::remove_calendar_entry;
+pub use calendar::update_calendar;
+
+pub use chat::download_message;
 pub use chat::get_all_toppic;
 pub use chat::get_all_messages_by_header;
 pub use chat::get_message_count_by_header;
 pub use chat::get_mqtt_user;
 pub use chat::get_page_messages_by_header;
+pub use chat::remove_topic;
 pub use chat::stand_alone_message;
 pub use chat::send;
+pub use chat::send_status;
 pub use chat::subscribe_topic;
+pub use chat::update_topic;
 pub use chat::ws_handler;
 pub use chat::PageParam;
 
 pub use file::upload_crt;
 pub use file::upload_script;
 
+pub use metrics::handler as metrics_handler;
+
+pub use mqtt::status as mqtt_status;
+
+pub use outbox::discard_outbox_message;
+pub use outbox::get_outbox;
+pub use outbox::retry_outbox_message;
+
 pub use sys::get_config;
 pub use sys::guide_finish;
 pub use sys::update_config;
 
+pub use secret::get_all_secret;
+pub use secret::remove_secret;
+pub use secret::save_secret;
+
 pub use session::guide;
 pub use session::handler as session_handler;
 pub use session::session;
@@ -38,8 +66,13 @@ pub use session::session;
 pub use task::add_task;
 pub use task::get_all_script_file_name;
 pub use task::get_all_task;
+pub use task::get_task_runs;
+pub use task::get_upcoming_runs;
+pub use task::pause_task;
 pub use task::remove_task;
 pub use task::restart_task;
+pub use task::resume_task;
+pub use task::run_script_tests;
 pub use task::start_task;
 pub use task::stop_task;
 pub use task::update_task;
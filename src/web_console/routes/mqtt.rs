@@ -0,0 +1,16 @@
+use axum::{response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::config::DEFAULT_CONNECTION;
+
+/// State of the broker connections, `status` is the one of the default connection
+#[allow(clippy::unused_async)]
+pub async fn status() -> impl IntoResponse {
+    let connections = crate::mqtt::status();
+    let status = connections
+        .iter()
+        .find(|status| status.name == DEFAULT_CONNECTION)
+        .cloned()
+        .unwrap_or_default();
+    Json(json!({"result": "ok", "status": status, "connections": connections}))
+}
@@ -0,0 +1,40 @@
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::outbox::{OutboxMessage, OutboxStatus};
+
+/// Messages listed at most
+const OUTBOX_LIMIT: i64 = 500;
+
+#[derive(Deserialize)]
+pub struct OutboxParams {
+    /// every status when unset
+    status: Option<OutboxStatus>,
+}
+
+/// The latest queued messages with their status
+pub async fn get_outbox(Query(params): Query<OutboxParams>) -> impl IntoResponse {
+    match OutboxMessage::get_all_outbox(params.status, OUTBOX_LIMIT).await {
+        Ok(messages) => Json(json!({"result": "ok", "messages": messages})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn retry_outbox_message(Path(id): Path<String>) -> impl IntoResponse {
+    match OutboxMessage::retry(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn discard_outbox_message(Path(id): Path<String>) -> impl IntoResponse {
+    match OutboxMessage::discard(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
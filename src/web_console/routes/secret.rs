@@ -0,0 +1,32 @@
+use axum::{extract::Path, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::secret::Secret;
+
+/// Names and remarks only, values never leave the server
+pub async fn get_all_secret() -> impl IntoResponse {
+    match Secret::get_all_secret().await {
+        Ok(secrets) => Json(json!({"result": "ok", "secrets": secrets})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+/// Create the secret or replace the value of an existing one
+pub async fn save_secret(Json(secret): Json<Secret>) -> impl IntoResponse {
+    let result = async {
+        secret.check()?;
+        secret.save().await
+    };
+
+    match result.await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn remove_secret(Path(name): Path<String>) -> impl IntoResponse {
+    match Secret::remove(&name).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
@@ -1,17 +1,73 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Json,
 };
+use chrono::{DateTime, Local};
+use serde::Deserialize;
 use serde_json::json;
 
-use crate::task::Task;
+use crate::{
+    script::{test_runner::TEST_SUFFIX, SCRIPT_EXTENSIONS},
+    task::{Task, TaskRun},
+};
 
 pub async fn get_all_task(State(task_manager): State<crate::TaskManager>) -> impl IntoResponse {
-    match task_manager.get_all_task() {
-        Ok(tasks) => Json(
-            json!({"result": "ok", "tasks": tasks.into_iter().map(|(running,task)| json!({"task":task,"running":running})).collect::<Vec<_>>()}),
-        ),
+    let result = async {
+        let mut last_runs = TaskRun::get_last_runs()
+            .await?
+            .into_iter()
+            .map(|run| (run.task_id.clone(), run))
+            .collect::<HashMap<_, _>>();
+
+        let now = Local::now();
+        let mut tasks = Vec::new();
+        for (running, task) in task_manager.get_all_task()? {
+            let next_run = if running && !task.paused {
+                task.upcoming_runs(now, None, 1).await?.pop()
+            } else {
+                None
+            };
+            tasks.push(json!({
+                "remaining": task.remaining_executions(),
+                "last_run": last_runs.remove(&task.id),
+                "next_run": next_run,
+                "task": task,
+                "running": running,
+            }));
+        }
+        anyhow::Ok(tasks)
+    };
+
+    match result.await {
+        Ok(tasks) => Json(json!({"result": "ok", "tasks": tasks})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpcomingParams {
+    /// defaults to now
+    from: Option<DateTime<Local>>,
+    /// defaults to a day after `from`
+    to: Option<DateTime<Local>>,
+}
+
+/// Fire times of all running tasks in a window, for the schedule timeline
+pub async fn get_upcoming_runs(
+    State(task_manager): State<crate::TaskManager>,
+    Query(params): Query<UpcomingParams>,
+) -> impl IntoResponse {
+    let from = params.from.unwrap_or_else(Local::now);
+    let to = params.to.unwrap_or(from + chrono::Duration::days(1));
+    if to < from {
+        return Json(json!({"result": "error", "message": "to is before from"}));
+    }
+
+    match task_manager.upcoming_runs(from, to).await {
+        Ok(runs) => Json(json!({"result": "ok", "from": from, "to": to, "runs": runs})),
         Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
     }
 }
@@ -66,6 +122,26 @@ pub async fn start_task(
     }
 }
 
+pub async fn pause_task(
+    Path(id): Path<String>,
+    State(task_manager): State<crate::TaskManager>,
+) -> impl IntoResponse {
+    match task_manager.pause_task(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+pub async fn resume_task(
+    Path(id): Path<String>,
+    State(task_manager): State<crate::TaskManager>,
+) -> impl IntoResponse {
+    match task_manager.resume_task(&id).await {
+        Ok(_) => Json(json!({"result": "ok"})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
 pub async fn update_task(
     State(task_manager): State<crate::TaskManager>,
     Path(id): Path<String>,
@@ -77,6 +153,31 @@ pub async fn update_task(
     }
 }
 
+pub async fn get_task_runs(Path(id): Path<String>) -> impl IntoResponse {
+    match TaskRun::get_by_task(&id).await {
+        Ok(runs) => Json(json!({"result": "ok", "runs": runs})),
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScriptTestParams {
+    filter: Option<String>,
+}
+
+pub async fn run_script_tests(
+    State(task_manager): State<crate::TaskManager>,
+    Query(params): Query<ScriptTestParams>,
+) -> impl IntoResponse {
+    match task_manager.run_script_tests(params.filter).await {
+        Ok(reports) => {
+            let passed = reports.iter().all(|report| report.passed());
+            Json(json!({"result": "ok", "passed": passed, "reports": reports}))
+        }
+        Err(e) => Json(json!({"result": "error", "message": e.to_string()})),
+    }
+}
+
 pub async fn get_all_script_file_name() -> impl IntoResponse {
     let path = crate::start_param.word_dir.join("script");
     let mut files = vec![];
@@ -84,9 +185,14 @@ pub async fn get_all_script_file_name() -> impl IntoResponse {
         for entry in path.read_dir().unwrap() {
             let entry = entry.unwrap();
             let path = entry.path();
-            let ex = path.extension();
-            if path.is_file() && ex.is_some() && ex.unwrap() == "lua" {
-                files.push(path.file_name().unwrap().to_str().unwrap().to_string());
+            let ex = path.extension().and_then(|ex| ex.to_str());
+            let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+            // tests are run by the test runner, not by tasks
+            if path.is_file()
+                && ex.is_some_and(|ex| SCRIPT_EXTENSIONS.contains(&ex))
+                && !file_name.ends_with(TEST_SUFFIX)
+            {
+                files.push(file_name);
             }
         }
     }
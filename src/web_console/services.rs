@@ -16,7 +16,7 @@ use tower_http::{services::ServeDir, trace::TraceLayer};
 use tower_sessions::{SessionManagerLayer, SessionStore};
 
 use crate::{
-    header::Header,
+    header::{Header, HeaderCommand},
     message::{RecMessage, SendMessage},
 };
 
@@ -53,7 +53,7 @@ pub fn backend<Store: SessionStore>(
     shared_state: Arc<store::Store>,
     send_msg_tx: UnboundedSender<SendMessage>,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
     task_manager: crate::TaskManager,
 ) -> Router {
     let session_service = ServiceBuilder::new()
@@ -90,7 +90,7 @@ fn back_public_route() -> Router<()> {
 fn back_auth_route(
     send_msg_tx: UnboundedSender<SendMessage>,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
     task_manager: crate::TaskManager,
 ) -> Router<()> {
     Router::new()
@@ -105,10 +105,11 @@ fn back_auth_route(
 fn back_token_route<S>(
     state: Arc<Store>,
     send_msg_tx: UnboundedSender<SendMessage>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
 ) -> Router<S> {
     Router::new()
         .route("/check", get(routes::api_handler))
+        .route("/metrics", get(routes::metrics_handler))
         .nest(
             "/a",
             back_chat_route_a(state.clone(), send_msg_tx, sub_header_tx),
@@ -123,7 +124,7 @@ fn back_token_route<S>(
 fn back_chat_route<S>(
     state: S,
     send_msg_tx: UnboundedSender<SendMessage>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
 ) -> Router<S>
 where
     S: Send + Sync + 'static + Clone,
@@ -141,16 +142,18 @@ fn back_chat_router_msg_send<S>(send_msg_tx: UnboundedSender<SendMessage>) -> Ro
         .with_state(send_msg_tx)
 }
 
-fn back_chat_router_topic<S>(sub_header_tx: UnboundedSender<Header>) -> Router<S> {
+fn back_chat_router_topic<S>(sub_header_tx: UnboundedSender<HeaderCommand>) -> Router<S> {
     Router::new()
         .route("/subscribe-topics", post(routes::subscribe_topic))
+        .route("/update-topic/:id", post(routes::update_topic))
+        .route("/topic/:id", delete(routes::remove_topic))
         .with_state(sub_header_tx)
 }
 
 fn back_chat_route_c(
     send_msg_tx: UnboundedSender<SendMessage>,
     rec_msg_tx: broadcast::Sender<(Header, RecMessage)>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
     task_manager: crate::TaskManager,
 ) -> Router<()> {
     Router::new()
@@ -159,11 +162,17 @@ fn back_chat_route_c(
         .merge(back_config_route())
         .merge(back_chat_route_ws(rec_msg_tx))
         .merge(back_chat_route_task(task_manager))
+        .merge(back_calendar_route())
+        .merge(back_secret_route())
+        .merge(back_outbox_route())
         .route("/msgs/:header", get(routes::get_all_messages_by_header))
         .route("/msg-count/:header", get(routes::get_message_count_by_header))
         .route("/page-msgs/:header", get(routes::get_page_messages_by_header))
         .route("/msg/:id", get(routes::stand_alone_message))
+        .route("/msg-raw/:id", get(routes::download_message))
+        .route("/send-status/:id", get(routes::send_status))
         .route("/get-mqtt-user", get(routes::get_mqtt_user))
+        .route("/mqtt/status", get(routes::mqtt_status))
         .route("/script-file-name", get(routes::get_all_script_file_name))
 }
 
@@ -175,10 +184,42 @@ fn back_chat_route_task<S>(task_manager: crate::TaskManager) -> Router<S> {
         .route("/stop-task/:id", get(routes::stop_task))
         .route("/restart-task/:id", get(routes::restart_task))
         .route("/start-task/:id", get(routes::start_task))
+        .route("/pause-task/:id", get(routes::pause_task))
+        .route("/resume-task/:id", get(routes::resume_task))
         .route("/update-task/:id", post(routes::update_task))
+        .route("/task-runs/:id", get(routes::get_task_runs))
+        .route("/task-upcoming", get(routes::get_upcoming_runs))
+        .route("/script-test", get(routes::run_script_tests))
         .with_state(task_manager)
 }
 
+fn back_calendar_route<S>() -> Router<S> {
+    Router::new()
+        .route("/calendar", get(routes::get_all_calendar))
+        .route("/calendar", post(routes::add_calendar))
+        .route("/calendar/:id", delete(routes::remove_calendar))
+        .route("/update-calendar/:id", post(routes::update_calendar))
+        .route("/calendar-entry/:id", post(routes::add_calendar_entry))
+        .route("/calendar-entry/:id", delete(routes::remove_calendar_entry))
+        .with_state(())
+}
+
+fn back_secret_route<S>() -> Router<S> {
+    Router::new()
+        .route("/secret", get(routes::get_all_secret))
+        .route("/secret", post(routes::save_secret))
+        .route("/secret/:name", delete(routes::remove_secret))
+        .with_state(())
+}
+
+fn back_outbox_route<S>() -> Router<S> {
+    Router::new()
+        .route("/outbox", get(routes::get_outbox))
+        .route("/outbox/:id", delete(routes::discard_outbox_message))
+        .route("/retry-outbox/:id", get(routes::retry_outbox_message))
+        .with_state(())
+}
+
 fn back_chat_route_ws<S>(rec_msg_tx: broadcast::Sender<(Header, RecMessage)>) -> Router<S> {
     Router::new()
         .route("/ws", get(routes::ws_handler))
@@ -211,7 +252,7 @@ fn back_config_route<S>() -> Router<S> {
 fn back_chat_route_a<S>(
     state: Arc<Store>,
     send_msg_tx: UnboundedSender<SendMessage>,
-    sub_header_tx: UnboundedSender<Header>,
+    sub_header_tx: UnboundedSender<HeaderCommand>,
 ) -> Router<S> {
     Router::new()
         .merge(back_chat_route(state.clone(), send_msg_tx, sub_header_tx))